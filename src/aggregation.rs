@@ -0,0 +1,181 @@
+use crate::dates::TimeBucket;
+use crate::json::find_value;
+use crate::output::RecordWriter;
+use crate::query::Query;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+// name of the column holding the number of matches in a group
+pub(crate) const COUNT_COLUMN: &str = "count";
+
+// something matches can be grouped by: a field, or a time bucket of created_utc
+pub(crate) enum GroupKey {
+    Field(String),
+    Time(TimeBucket),
+}
+
+impl GroupKey {
+    // hour, day, week, month and year are time buckets, everything else is a field name
+    pub(crate) fn parse(key: &str) -> Self {
+        match key.parse::<TimeBucket>() {
+            Ok(bucket) => GroupKey::Time(bucket),
+            Err(_) => GroupKey::Field(key.to_lowercase()),
+        }
+    }
+
+    // the value of the key as JSON text. Field values are copied from the line as they are, time
+    // buckets are quoted labels and missing values are null.
+    fn value_of(&self, line: &str) -> String {
+        match self {
+            GroupKey::Field(field) => find_value(line, field).unwrap_or("null").to_string(),
+            GroupKey::Time(bucket) => bucket
+                .label_of(line)
+                .map(|label| format!("\"{}\"", label))
+                .unwrap_or_else(|| "null".to_string()),
+        }
+    }
+}
+
+type Groups = HashMap<Vec<String>, u64>;
+
+// counts the matches per group instead of writing them. Chunks are counted in parallel into
+// partial counts which are then merged, at the end the counts are written as one row per group.
+pub(crate) struct Counts {
+    names: Vec<String>,
+    keys: Vec<GroupKey>,
+    groups: Groups,
+}
+
+impl Counts {
+    // without any keys all matches are counted in a single group
+    pub(crate) fn new(names: &[String]) -> Self {
+        Self {
+            names: names.to_vec(),
+            keys: names.iter().map(|name| GroupKey::parse(name)).collect(),
+            groups: HashMap::new(),
+        }
+    }
+
+    // counts the matches in a chunk, returns the number of matches
+    pub(crate) fn count_chunk(&mut self, lines: Vec<String>, query: &Query) -> usize {
+        let partial = lines
+            .par_iter()
+            .filter(|line| query.matches(line))
+            .fold(HashMap::new, |mut groups: Groups, line| {
+                let group = self.keys.iter().map(|key| key.value_of(line)).collect();
+                *groups.entry(group).or_insert(0) += 1;
+                groups
+            })
+            .reduce(HashMap::new, merge);
+        let matches = partial.values().sum::<u64>();
+        merge_into(&mut self.groups, partial);
+        matches as usize
+    }
+
+    // writes one JSON object per group, e.g. {"subreddit":"news","month":"2016-09","count":12},
+    // sorted by the group values. Table formats turn them into rows. Dotted fields are written as
+    // nested objects, e.g. {"media":{"type":"youtube.com"},"count":3}, so the table formats find
+    // them at the same path as in the dumps. Returns the number of groups.
+    pub(crate) fn write_to(&mut self, output: &mut dyn RecordWriter) -> io::Result<usize> {
+        let mut groups: Vec<(Vec<String>, u64)> = self.groups.drain().collect();
+        // a plain count is written even if nothing matched
+        if groups.is_empty() && self.keys.is_empty() {
+            groups.push((Vec::new(), 0));
+        }
+        groups.sort_unstable();
+        let group_count = groups.len();
+        for (group, count) in groups {
+            let mut row = Object::default();
+            for (name, value) in self.names.iter().zip(group) {
+                row.insert(&name.split('.').collect::<Vec<&str>>(), value);
+            }
+            row.insert(&[COUNT_COLUMN], count.to_string());
+            output.write_record(&row.to_string())?;
+        }
+        Ok(group_count)
+    }
+}
+
+// a JSON object being built from the group values, keys keep the order they were inserted in
+#[derive(Default)]
+struct Object {
+    entries: Vec<(String, Value)>,
+}
+
+enum Value {
+    Raw(String),
+    Object(Object),
+}
+
+impl Object {
+    fn insert(&mut self, path: &[&str], value: String) {
+        let [key, rest @ ..] = path else {
+            return;
+        };
+        if rest.is_empty() {
+            self.entries.push((key.to_string(), Value::Raw(value)));
+            return;
+        }
+        let position = self
+            .entries
+            .iter()
+            .position(|(name, value)| name == key && matches!(value, Value::Object(_)));
+        let position = position.unwrap_or_else(|| {
+            self.entries
+                .push((key.to_string(), Value::Object(Object::default())));
+            self.entries.len() - 1
+        });
+        if let Value::Object(object) = &mut self.entries[position].1 {
+            object.insert(rest, value);
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match value {
+                Value::Raw(raw) => write!(f, "\"{}\":{}", key, raw)?,
+                Value::Object(object) => write!(f, "\"{}\":{}", key, object)?,
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+fn merge(mut groups: Groups, other: Groups) -> Groups {
+    merge_into(&mut groups, other);
+    groups
+}
+
+fn merge_into(groups: &mut Groups, other: Groups) {
+    for (group, count) in other {
+        *groups.entry(group).or_insert(0) += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dotted_names_become_nested_objects() {
+        let mut row = Object::default();
+        row.insert(&["subreddit"], "\"news\"".to_string());
+        row.insert(&["media", "type"], "\"youtube.com\"".to_string());
+        row.insert(&["media", "oembed", "title"], "null".to_string());
+        row.insert(&[COUNT_COLUMN], "3".to_string());
+        let row = row.to_string();
+        assert_eq!(
+            row,
+            r#"{"subreddit":"news","media":{"type":"youtube.com","oembed":{"title":null}},"count":3}"#
+        );
+        assert_eq!(find_value(&row, "media.type"), Some("\"youtube.com\""));
+    }
+}
@@ -1,6 +1,12 @@
 extern crate clap;
 
 use crate::constants::get_presets;
+use crate::dates::TimeBucket;
+use crate::frames::MAX_FRAME_SIZE;
+use crate::inputs::DumpKind;
+use crate::output::{Compression, OutputFormat};
+use crate::parquet_output::Partitioning;
+use crate::sampling::Stratum;
 use clap::{value_parser, Arg, ArgAction, Command};
 
 pub fn get_preset_fields(preset: &str) -> Option<Vec<String>> {
@@ -23,17 +29,67 @@ pub fn get_preset_fields(preset: &str) -> Option<Vec<String>> {
     let args_fields = presets_map.get(preset).unwrap().to_vec();
     // convert all values in the vec from &str to String
     let search_fields = args_fields.iter().map(|s| s.to_string()).collect();
-    return Some(search_fields);
+    Some(search_fields)
+}
+
+// a sampling rate is a probability, 0 would never write anything
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate <= 1.0 => Ok(rate),
+        _ => Err(format!("{} is not a number between 0 and 1", value)),
+    }
+}
+
+// the subcommands, a plain invocation searches
+pub enum Subcommand {
+    // writes a block index for each input file
+    Index {
+        input: Vec<String>,
+        block_lines: u64,
+    },
+    // rewrites each input file in the zstd seekable format
+    Convert {
+        input: Vec<String>,
+        output_dir: String,
+        frame_size: usize,
+        level: i32,
+        overwrite: bool,
+    },
 }
 
 pub struct CommandLineArgs {
-    pub input: String,
+    pub subcommand: Option<Subcommand>,
+    pub input: Vec<String>,
+    pub dump_dir: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub kind: DumpKind,
     pub output: String,
     pub fields: Option<Vec<String>>,
+    pub select: Option<Vec<String>>,
+    pub count: bool,
+    pub group_by: Option<Vec<String>>,
+    pub timeseries: Option<TimeBucket>,
+    pub split_by: Option<String>,
+    pub timeseries_output: String,
+    pub format: OutputFormat,
+    pub schema: Vec<String>,
+    pub partition_by: Option<Partitioning>,
+    pub fts: bool,
+    pub compress: Option<Compression>,
+    pub compression_level: Option<i32>,
+    pub compression_threads: u32,
+    pub query: Option<String>,
+    pub after: Option<String>,
+    pub before: Option<String>,
     pub append: bool,
     pub chunk_size: usize,
     pub overwrite: bool,
-    pub random: bool,
+    pub limit: Option<usize>,
+    pub sample_rate: Option<f64>,
+    pub sample_size: Option<usize>,
+    pub stratify_by: Option<Stratum>,
+    pub seed: Option<u64>,
     pub linecount: bool,
     pub preset: Option<String>,
     pub verbose: bool,
@@ -47,20 +103,116 @@ impl CommandLineArgs {
             .version(env!("CARGO_PKG_VERSION"))
             .author("Luc Aggett (luc@aggett.com")
             .arg_required_else_help(true)
+            .subcommand_negates_reqs(true)
+            .subcommand(Command::new("index")
+                .about("Writes a block index next to each input file (<input>.rsidx). Searches for exact subreddits or authors, e.g. subreddit:^news$, use it to skip blocks of lines that can't match.")
+                .arg(Arg::new("input")
+                    .value_name("INPUT")
+                    .help("The files to index. Accepts multiple files, directories and glob patterns like 'RC_2016-*.zst'.")
+                    .required(true)
+                    .action(ArgAction::Append)
+                    .num_args(1..)
+                )
+                .arg(Arg::new("block-lines")
+                    .long("block-lines")
+                    .value_name("LINES")
+                    .help("Sets the number of lines per block. Smaller blocks can be skipped more often but make the index larger.")
+                    .required(false)
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .default_value("100000")
+                )
+            )
+            .subcommand(Command::new("convert")
+                .about("Rewrites each input file in the zstd seekable format: independent frames followed by a seek table. The converted files can still be read by any zstd decoder, searches with a block index skip blocks without decompressing them.")
+                .arg(Arg::new("input")
+                    .value_name("INPUT")
+                    .help("The files to convert. Accepts multiple files, directories and glob patterns like 'RC_2016-*.zst'.")
+                    .required(true)
+                    .action(ArgAction::Append)
+                    .num_args(1..)
+                )
+                .arg(Arg::new("output-dir")
+                    .long("output-dir")
+                    .value_name("DIR")
+                    .help("Sets the directory the converted files are written to, they keep the name of the input file.")
+                    .required(true)
+                    .action(ArgAction::Set)
+                )
+                .arg(Arg::new("frame-size")
+                    .long("frame-size")
+                    .value_name("MB")
+                    .help("Sets the decompressed size of each frame in megabytes, at most 16. Smaller frames allow finer skipping but compress slightly worse.")
+                    .required(false)
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(u64).range(1..=MAX_FRAME_SIZE / (1024 * 1024)))
+                    .default_value("16")
+                )
+                .arg(Arg::new("level")
+                    .long("level")
+                    .value_name("LEVEL")
+                    .help("Sets the zstd compression level.")
+                    .required(false)
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(i32).range(1..=22))
+                    .default_value("9")
+                )
+                .arg(Arg::new("overwrite")
+                    .short('w')
+                    .long("overwrite")
+                    .help("Replaces files that already exist in the output directory instead of skipping them.")
+                    .required(false)
+                    .action(ArgAction::SetTrue)
+                )
+            )
             .arg(Arg::new("input")
                      .short('i')
                      .long("input")
                      .value_name("INPUT")
-                     .help("Sets the input file to use. Must be a zstd compressed newline delimited JSON file.")
-                     .required(true)
-                     .action(ArgAction::Set)
-                     .num_args(1),
+                     .help("Sets the input files to use. Must be newline delimited JSON files, either zstd, gzip, bzip2 or xz compressed or uncompressed. Use - to read from stdin. Accepts multiple files, directories (all .zst files inside are searched) and glob patterns like 'RC_2016-*.zst'. Can be specified multiple times.")
+                     .required_unless_present("dump-dir")
+                     .action(ArgAction::Append)
+                     .num_args(1..),
+            )
+            .arg(Arg::new("dump-dir")
+                .long("dump-dir")
+                .value_name("DIRECTORY")
+                .help("Directory containing the monthly RC_YYYY-MM.zst / RS_YYYY-MM.zst dumps (directly or in comments/ and submissions/ subdirectories). The files between --from and --to are searched.")
+                .required(false)
+                .action(ArgAction::Set)
+                .num_args(1)
+            )
+            .arg(Arg::new("from")
+                .long("from")
+                .value_name("YYYY-MM")
+                .help("First month to search in --dump-dir. Defaults to the first month found.")
+                .requires("dump-dir")
+                .action(ArgAction::Set)
+                .num_args(1)
+            )
+            .arg(Arg::new("to")
+                .long("to")
+                .value_name("YYYY-MM")
+                .help("Last month to search in --dump-dir (inclusive). Defaults to the last month found.")
+                .requires("dump-dir")
+                .action(ArgAction::Set)
+                .num_args(1)
+            )
+            .arg(Arg::new("kind")
+                .long("kind")
+                .value_name("KIND")
+                .help("Which dumps to search in --dump-dir.")
+                .requires("dump-dir")
+                .action(ArgAction::Set)
+                .value_parser(["comments", "submissions"])
+                .default_value("comments")
+                .num_args(1)
             )
             .arg(Arg::new("output")
                      .short('o')
                      .long("output")
                      .value_name("OUTPUT")
-                     .help("Sets the output file to use.")
+                     .help("Sets the output file to use. Use - to write the matches to stdout, progress and messages are then written to stderr.")
                      .action(ArgAction::Set)
                      .num_args(1)
                      .default_value("reddit_comments.json"),
@@ -69,13 +221,164 @@ impl CommandLineArgs {
                 .short('f')
                 .long("fields")
                 .value_name("FIELDS")
-                .help("Sets the fields to search. Must be in the format <field>:<regex>. Can be specified multiple times.")
+                .help("Sets the fields to search. Must be in the format <field>:<regex>, e.g. author:^auto.*bot$. The regex is matched case insensitively against the value of the field. Numeric fields can also be compared with <field>>=<number> (or >, <, <=, =) or limited to a range with <field>:<min>..<max>. Can be specified multiple times.")
                 .required_unless_present("preset")
+                .required_unless_present("query")
+                .required_unless_present("after")
+                .required_unless_present("before")
                 .required_unless_present("linecount")
                 .action(ArgAction::Set)
                 .value_parser(value_parser!(String))
                 .num_args(1..)
             )
+            .arg(Arg::new("query")
+                .short('q')
+                .long("query")
+                .value_name("QUERY")
+                .help("Sets a query combining <field>:<regex> terms with AND, OR, NOT and parentheses, e.g. 'subreddit:^politics$ AND (body:vaccin OR body:\"mask mandate\")'. Terms without an operator between them are combined with AND. If fields or a preset are given as well, only their matches that also match the query are kept.")
+                .required_unless_present("fields")
+                .required_unless_present("preset")
+                .required_unless_present("after")
+                .required_unless_present("before")
+                .required_unless_present("linecount")
+                .action(ArgAction::Set)
+                .num_args(1)
+            )
+            .arg(Arg::new("after")
+                .long("after")
+                .value_name("DATE")
+                .help("Only keep items created at or after this date, e.g. 2016-09-01 or 2016-09-01T12:00:00+02:00. Dates without a timezone are UTC.")
+                .required(false)
+                .action(ArgAction::Set)
+                .num_args(1)
+            )
+            .arg(Arg::new("before")
+                .long("before")
+                .value_name("DATE")
+                .help("Only keep items created before this date, e.g. 2016-11-09. A date without a time excludes that whole day.")
+                .required(false)
+                .action(ArgAction::Set)
+                .num_args(1)
+            )
+            .arg(Arg::new("select")
+                .short('s')
+                .long("select")
+                .value_name("FIELDS")
+                .help("Only write these fields of each match instead of the whole line, e.g. id,author,subreddit,created_utc,body. Nested fields can be selected with dots, e.g. media.oembed.title. Missing fields are written as null.")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .num_args(1..)
+            )
+            .arg(Arg::new("count")
+                .long("count")
+                .help("Only counts the matches instead of writing them. The count (or one row per group with --group-by) is written to the output in the chosen format.")
+                .required(false)
+                .conflicts_with_all(["select", "limit", "sample-rate", "sample-size", "random", "partition-by"])
+                .action(ArgAction::SetTrue)
+            )
+            .arg(Arg::new("group-by")
+                .long("group-by")
+                .value_name("KEYS")
+                .help("Counts the matches per group instead of writing them, e.g. subreddit,month. Keys are fields or the time buckets hour, day, week, month and year of created_utc.")
+                .required(false)
+                .conflicts_with_all(["select", "limit", "sample-rate", "sample-size", "random", "partition-by"])
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .num_args(1..)
+            )
+            .arg(Arg::new("timeseries")
+                .long("timeseries")
+                .value_name("BUCKET")
+                .help("Also writes the number of matches per hour, day, week or month of created_utc to a CSV report (see --timeseries-output). Buckets without matches are included with a count of 0.")
+                .required(false)
+                .conflicts_with_all(["count", "group-by"])
+                .action(ArgAction::Set)
+                .value_parser(["hour", "day", "week", "month"])
+                .num_args(1)
+            )
+            .arg(Arg::new("split-by")
+                .long("split-by")
+                .value_name("FIELD")
+                .help("Splits the time series by the values of a field, e.g. subreddit.")
+                .required(false)
+                .requires("timeseries")
+                .action(ArgAction::Set)
+                .num_args(1)
+            )
+            .arg(Arg::new("timeseries-output")
+                .long("timeseries-output")
+                .value_name("FILE")
+                .help("Sets the file the time series report is written to.")
+                .required(false)
+                .requires("timeseries")
+                .action(ArgAction::Set)
+                .default_value("timeseries.csv")
+                .num_args(1)
+            )
+            .arg(Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Sets the output format. json writes the matching lines (or the selected fields) as newline delimited JSON, csv and tsv write a table with a header row, parquet writes a typed parquet file and sqlite writes a table into a SQLite database (named comments or submissions, with the whole line in the raw column). The columns of tables are the selected fields, or a default set for comments or submissions.")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(["json", "csv", "tsv", "parquet", "sqlite"])
+                .default_value("json")
+                .num_args(1)
+            )
+            .arg(Arg::new("schema")
+                .long("schema")
+                .value_name("SCHEMA")
+                .help("Declares column types for parquet and sqlite output in the format <field>:<type>, where type is int, float, bool or string, e.g. edited:string,score:int. Columns not listed here are typed by their field name (integers for score, created_utc etc., strings for text).")
+                .required(false)
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .num_args(1..)
+            )
+            .arg(Arg::new("partition-by")
+                .long("partition-by")
+                .value_name("FIELD")
+                .help("Writes parquet output as a directory partitioned by subreddit or month, e.g. <output>/subreddit=news/part-00000.parquet.")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(["subreddit", "month"])
+                .num_args(1)
+            )
+            .arg(Arg::new("fts")
+                .long("fts")
+                .help("Also create an FTS5 full text index over body, title and selftext for sqlite output.")
+                .required(false)
+                .action(ArgAction::SetTrue)
+            )
+            .arg(Arg::new("compress")
+                .long("compress")
+                .value_name("COMPRESSION")
+                .help("Compresses json, csv and tsv output. Defaults to zstd for output files ending in .zst, gzip for .gz and none otherwise.")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(["none", "zstd", "gzip"])
+                .num_args(1)
+            )
+            .arg(Arg::new("compression-level")
+                .long("compression-level")
+                .value_name("LEVEL")
+                .help("Sets the compression level. Defaults to 3 for zstd (1-22) and 6 for gzip (0-9).")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(i32))
+                .allow_negative_numbers(true)
+                .num_args(1)
+            )
+            .arg(Arg::new("compression-threads")
+                .long("compression-threads")
+                .value_name("THREADS")
+                .help("Sets the number of threads used for zstd compression. Defaults to 1.")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u32))
+                .default_value("1")
+                .num_args(1)
+            )
             .arg(Arg::new("append")
                      .short('a')
                      .long("append")
@@ -92,12 +395,65 @@ impl CommandLineArgs {
                      .conflicts_with("append")
                      .action(ArgAction::SetTrue),
             )
+            .arg(Arg::new("limit")
+                .long("limit")
+                .value_name("N")
+                .help("Stops after N matches. Reading stops as soon as the limit is reached, which is useful for trying out a query. When sampling, the sample is drawn from the first N matches.")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u64).range(1..))
+                .num_args(1)
+            )
+            .arg(Arg::new("sample-rate")
+                .long("sample-rate")
+                .value_name("RATE")
+                .help("Writes each match with the given probability, e.g. 0.01 for a 1% sample of the matches.")
+                .required(false)
+                .conflicts_with("sample-size")
+                .action(ArgAction::Set)
+                .value_parser(parse_rate)
+                .num_args(1)
+            )
+            // -r/--random was a flag that never did anything, it is kept so existing scripts still run
             .arg(Arg::new("random")
-                     .short('r')
-                     .long("random")
-                     .help("Randomly sample the input file. Useful for testing.")
-                     .required(false)
-                     .action(ArgAction::SetTrue),
+                .short('r')
+                .long("random")
+                .value_name("RATE")
+                .help("Deprecated, use --sample-rate. With a rate it is the same as --sample-rate, without one it has no effect.")
+                .hide(true)
+                .required(false)
+                .conflicts_with_all(["sample-rate", "sample-size"])
+                .action(ArgAction::Set)
+                .value_parser(parse_rate)
+                .num_args(0..=1)
+            )
+            .arg(Arg::new("sample-size")
+                .long("sample-size")
+                .value_name("SIZE")
+                .help("Writes a uniform random sample of this many matches, in the order they appear in the input.")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u64).range(1..))
+                .num_args(1)
+            )
+            .arg(Arg::new("stratify-by")
+                .long("stratify-by")
+                .value_name("STRATUM")
+                .help("Draws the --sample-size sample separately for every subreddit, author, day, week or month, so each of them contributes up to that many matches.")
+                .required(false)
+                .requires("sample-size")
+                .action(ArgAction::Set)
+                .value_parser(["subreddit", "author", "day", "week", "month"])
+                .num_args(1)
+            )
+            .arg(Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seeds the random sampling so the same sample is drawn again. A random seed is used by default, it is shown with --verbose.")
+                .required(false)
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(u64))
+                .num_args(1)
             )
             .arg(Arg::new("linecount")
                      .short('l')
@@ -110,8 +466,11 @@ impl CommandLineArgs {
                 .short('p')
                 .long("preset")
                 .value_name("PRESET")
-                .help("Use a preset instead of specifying fields manually. Available presets are: en_news, en_politics, en_hate_speech")
+                .help("Use a preset instead of specifying fields manually. Available presets are: en_news, en_politics, en_science, en_hate_speech, qa_subreddits, controversial (comments only), en_news_domains, self_posts and nsfw_submissions (submissions only).")
                 .required_unless_present("fields")
+                .required_unless_present("query")
+                .required_unless_present("after")
+                .required_unless_present("before")
                 .required_unless_present("linecount")
                 .action(ArgAction::Set)
                 .num_args(1)
@@ -143,12 +502,56 @@ impl CommandLineArgs {
                 .value_parser(value_parser!(usize)))
             .get_matches();
 
+        if args.contains_id("random") {
+            eprintln!(
+                "Warning: -r/--random is deprecated, use --sample-rate or --sample-size instead."
+            );
+        }
+
+        let subcommand = match args.subcommand() {
+            Some(("index", index_args)) => Some(Subcommand::Index {
+                input: index_args
+                    .get_many::<String>("input")
+                    .map_or_else(Vec::new, |values| {
+                        values.map(|input| input.replace("\\", "/")).collect()
+                    }),
+                block_lines: *index_args.get_one::<u64>("block-lines").unwrap_or(&100_000),
+            }),
+            Some(("convert", convert_args)) => Some(Subcommand::Convert {
+                input: convert_args
+                    .get_many::<String>("input")
+                    .map_or_else(Vec::new, |values| {
+                        values.map(|input| input.replace("\\", "/")).collect()
+                    }),
+                output_dir: convert_args
+                    .get_one::<String>("output-dir")
+                    .unwrap()
+                    .to_string(),
+                frame_size: *convert_args.get_one::<u64>("frame-size").unwrap_or(&16) as usize
+                    * 1024
+                    * 1024,
+                level: *convert_args.get_one::<i32>("level").unwrap_or(&9),
+                overwrite: *convert_args.get_one("overwrite").unwrap_or(&false),
+            }),
+            _ => None,
+        };
+
         // Extract values from args
         Ok(Self {
+            subcommand,
             input: args
-                .get_one::<String>("input")
-                .ok_or("Failed to parse input path, double check the arguments")?
-                .replace("\\", "/"),
+                .get_many::<String>("input")
+                .map_or_else(Vec::new, |values| {
+                    values.map(|input| input.replace("\\", "/")).collect()
+                }),
+            dump_dir: args
+                .get_one::<String>("dump-dir")
+                .map(|dump_dir| dump_dir.replace("\\", "/")),
+            from: args.get_one::<String>("from").cloned(),
+            to: args.get_one::<String>("to").cloned(),
+            kind: args
+                .get_one::<String>("kind")
+                .map_or(Ok(DumpKind::Comments), |kind| kind.parse())?,
             output: args
                 .get_one::<String>("output")
                 .ok_or("Failed to parse output path, double check the arguments")?
@@ -157,9 +560,61 @@ impl CommandLineArgs {
                 args.get_many::<String>("fields")
                     .map_or_else(Vec::new, |values| values.map(ToString::to_string).collect()),
             ),
+            select: args
+                .get_many::<String>("select")
+                .map(|values| values.map(|field| field.trim().to_string()).collect()),
+            count: *args.get_one("count").unwrap_or(&false),
+            group_by: args
+                .get_many::<String>("group-by")
+                .map(|values| values.map(|key| key.trim().to_string()).collect()),
+            timeseries: args
+                .get_one::<String>("timeseries")
+                .map(|bucket| bucket.parse())
+                .transpose()?,
+            split_by: args
+                .get_one::<String>("split-by")
+                .map(|field| field.to_lowercase()),
+            timeseries_output: args.get_one::<String>("timeseries-output").map_or_else(
+                || "timeseries.csv".to_string(),
+                |report| report.replace("\\", "/"),
+            ),
+            format: args
+                .get_one::<String>("format")
+                .map_or(Ok(OutputFormat::Json), |format| format.parse())?,
+            schema: args
+                .get_many::<String>("schema")
+                .map_or_else(Vec::new, |values| {
+                    values.map(|entry| entry.trim().to_string()).collect()
+                }),
+            partition_by: args
+                .get_one::<String>("partition-by")
+                .map(|partitioning| partitioning.parse())
+                .transpose()?,
+            fts: *args.get_one("fts").unwrap_or(&false),
+            compress: args
+                .get_one::<String>("compress")
+                .map(|compression| compression.parse())
+                .transpose()?,
+            compression_level: args.get_one::<i32>("compression-level").copied(),
+            compression_threads: *args.get_one::<u32>("compression-threads").unwrap_or(&1),
+            query: args.get_one::<String>("query").cloned(),
+            after: args.get_one::<String>("after").cloned(),
+            before: args.get_one::<String>("before").cloned(),
             append: *args.get_one("append").unwrap_or(&false),
             overwrite: *args.get_one("overwrite").unwrap_or(&false),
-            random: *args.get_one("random").unwrap_or(&false),
+            limit: args.get_one::<u64>("limit").map(|limit| *limit as usize),
+            sample_rate: args
+                .get_one::<f64>("sample-rate")
+                .or(args.get_one::<f64>("random"))
+                .copied(),
+            sample_size: args
+                .get_one::<u64>("sample-size")
+                .map(|size| *size as usize),
+            stratify_by: args
+                .get_one::<String>("stratify-by")
+                .map(|stratum| stratum.parse())
+                .transpose()?,
+            seed: args.get_one::<u64>("seed").copied(),
             chunk_size: *args.get_one("chunk-size").unwrap_or(&100_000),
             linecount: *args.get_one("linecount").unwrap_or(&false),
             preset: args.get_one::<String>("preset").cloned(),
@@ -0,0 +1,206 @@
+use crate::json::{find_value, value_text};
+use chrono::{DateTime, Datelike, Months, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use std::str::FromStr;
+
+// formats accepted for a point in time without a timezone, these are taken to be UTC
+const NAIVE_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+// formats accepted for a point in time with a timezone, in addition to RFC 3339
+const OFFSET_FORMATS: [&str; 3] = [
+    "%Y-%m-%d %H:%M:%S%:z",
+    "%Y-%m-%dT%H:%M%:z",
+    "%Y-%m-%d %H:%M%:z",
+];
+
+// parses a human readable date into unix timestamps, as used by created_utc. Returns the start and
+// end of the period the value covers: a month (2016-09) or a day (2016-09-01) covers the whole
+// month or day, a datetime (2016-09-01T12:00:00+02:00) is a single point where start and end are
+// the same.
+pub(crate) fn parse_date(value: &str) -> Option<(i64, i64)> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        let timestamp = datetime.timestamp();
+        return Some((timestamp, timestamp));
+    }
+    for format in OFFSET_FORMATS {
+        if let Ok(datetime) = DateTime::parse_from_str(value, format) {
+            let timestamp = datetime.timestamp();
+            return Some((timestamp, timestamp));
+        }
+    }
+    for format in NAIVE_FORMATS {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            let timestamp = datetime.and_utc().timestamp();
+            return Some((timestamp, timestamp));
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let start = date.and_hms_opt(0, 0, 0)?.and_utc();
+        return Some((start.timestamp(), (start + TimeDelta::days(1)).timestamp()));
+    }
+    // a month on its own, e.g. 2016-09
+    if let Ok(date) = NaiveDate::parse_from_str(&format!("{}-01", value), "%Y-%m-%d") {
+        let start = date.and_hms_opt(0, 0, 0)?.and_utc();
+        let end = start.checked_add_months(Months::new(1))?;
+        return Some((start.timestamp(), end.timestamp()));
+    }
+    None
+}
+
+// the creation time of a line as a unix timestamp. Older dumps store created_utc as a string.
+pub(crate) fn created_utc(line: &str) -> Option<i64> {
+    find_value(line, "created_utc")
+        .and_then(|raw| value_text(raw).trim().parse::<f64>().ok())
+        .map(|timestamp| timestamp as i64)
+}
+
+// a period of time that matches are grouped into by created_utc
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TimeBucket {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl FromStr for TimeBucket {
+    type Err = String;
+
+    fn from_str(bucket: &str) -> Result<Self, Self::Err> {
+        match bucket {
+            "hour" => Ok(TimeBucket::Hour),
+            "day" => Ok(TimeBucket::Day),
+            "week" => Ok(TimeBucket::Week),
+            "month" => Ok(TimeBucket::Month),
+            "year" => Ok(TimeBucket::Year),
+            _ => Err(format!(
+                "Unknown time bucket {}, must be hour, day, week, month or year",
+                bucket
+            )),
+        }
+    }
+}
+
+impl TimeBucket {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            TimeBucket::Hour => "hour",
+            TimeBucket::Day => "day",
+            TimeBucket::Week => "week",
+            TimeBucket::Month => "month",
+            TimeBucket::Year => "year",
+        }
+    }
+
+    // labels sort in time order, weeks are ISO weeks like 2016-W35
+    pub(crate) fn label(&self, timestamp: i64) -> Option<String> {
+        let format = match self {
+            TimeBucket::Hour => "%Y-%m-%dT%H:00",
+            TimeBucket::Day => "%Y-%m-%d",
+            TimeBucket::Week => "%G-W%V",
+            TimeBucket::Month => "%Y-%m",
+            TimeBucket::Year => "%Y",
+        };
+        DateTime::from_timestamp(timestamp, 0).map(|datetime| datetime.format(format).to_string())
+    }
+
+    // the start of the bucket containing the timestamp
+    pub(crate) fn start(&self, timestamp: i64) -> Option<DateTime<Utc>> {
+        let datetime = DateTime::from_timestamp(timestamp, 0)?;
+        let day = datetime.date_naive();
+        let start = match self {
+            TimeBucket::Hour => {
+                return DateTime::from_timestamp(timestamp - timestamp.rem_euclid(3600), 0)
+            }
+            TimeBucket::Day => day,
+            TimeBucket::Week => day - TimeDelta::days(day.weekday().num_days_from_monday() as i64),
+            TimeBucket::Month => day.with_day(1)?,
+            TimeBucket::Year => day.with_ordinal(1)?,
+        };
+        Some(start.and_hms_opt(0, 0, 0)?.and_utc())
+    }
+
+    // the start of the bucket following the one starting at `start`
+    pub(crate) fn next(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            TimeBucket::Hour => start + TimeDelta::hours(1),
+            TimeBucket::Day => start + TimeDelta::days(1),
+            TimeBucket::Week => start + TimeDelta::weeks(1),
+            TimeBucket::Month => start + Months::new(1),
+            TimeBucket::Year => start + Months::new(12),
+        }
+    }
+
+    // the label of the bucket the line was created in
+    pub(crate) fn label_of(&self, line: &str) -> Option<String> {
+        created_utc(line).and_then(|timestamp| self.label(timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2016-09-01T00:00:00Z
+    const SEP_1: i64 = 1472688000;
+
+    #[test]
+    fn days_and_months_cover_the_whole_period() {
+        assert_eq!(parse_date("2016-09-01"), Some((SEP_1, SEP_1 + 86400)));
+        assert_eq!(parse_date("2016-09"), Some((SEP_1, SEP_1 + 30 * 86400)));
+        // 2016-12-01 to 2017-01-01
+        assert_eq!(parse_date("2016-12"), Some((1480550400, 1483228800)));
+        // 2016 is a leap year
+        assert_eq!(
+            parse_date("2016-02"),
+            Some((1454284800, 1454284800 + 29 * 86400))
+        );
+    }
+
+    #[test]
+    fn datetimes_without_a_timezone_are_utc() {
+        let noon = Some((SEP_1 + 12 * 3600, SEP_1 + 12 * 3600));
+        assert_eq!(parse_date("2016-09-01T12:00:00"), noon);
+        assert_eq!(parse_date("2016-09-01 12:00:00"), noon);
+        assert_eq!(parse_date("2016-09-01T12:00"), noon);
+        assert_eq!(parse_date("2016-09-01 12:00"), noon);
+    }
+
+    #[test]
+    fn datetimes_with_a_timezone_offset() {
+        let noon = Some((SEP_1 + 12 * 3600, SEP_1 + 12 * 3600));
+        assert_eq!(parse_date("2016-09-01T12:00:00Z"), noon);
+        assert_eq!(parse_date("2016-09-01T14:00:00+02:00"), noon);
+        assert_eq!(parse_date("2016-09-01 07:00:00-05:00"), noon);
+        assert_eq!(parse_date("2016-09-01T14:00+02:00"), noon);
+        assert_eq!(parse_date("2016-09-01 14:00+02:00"), noon);
+        // the offset can move the point in time to another day
+        assert_eq!(
+            parse_date("2016-09-01T01:00:00+02:00"),
+            Some((SEP_1 - 3600, SEP_1 - 3600))
+        );
+    }
+
+    #[test]
+    fn rejects_other_values() {
+        assert_eq!(parse_date(""), None);
+        assert_eq!(parse_date("2016"), None);
+        assert_eq!(parse_date("2016-13"), None);
+        assert_eq!(parse_date("2016-09-31"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn created_utc_as_number_or_string() {
+        assert_eq!(created_utc(r#"{"created_utc":1472688000}"#), Some(SEP_1));
+        assert_eq!(created_utc(r#"{"created_utc":"1472688000"}"#), Some(SEP_1));
+        assert_eq!(created_utc(r#"{"created_utc":1472688000.0}"#), Some(SEP_1));
+        assert_eq!(created_utc(r#"{"created_utc":"soon"}"#), None);
+        assert_eq!(created_utc(r#"{"id":"x"}"#), None);
+    }
+}
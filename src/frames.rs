@@ -0,0 +1,339 @@
+use crate::inputs::CountingReader;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+// zstd files made of many independent frames (written by pzstd, `zstd -T` with --rsyncable, or
+// the convert subcommand) can be decompressed a batch of frames at a time on the rayon pool
+// instead of by a single decoder. The frames are found by walking the frame and block headers.
+// See https://github.com/facebook/zstd/blob/dev/doc/zstd_compression_format.md
+
+const ZSTD_MAGIC: u32 = 0xFD2FB528;
+// skippable frames use any magic from 0x184D2A50 to 0x184D2A5F
+const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+const SKIPPABLE_MASK: u32 = 0xFFFFFFF0;
+// files with frames that decompress to more than this are decompressed as a stream, the single
+// frame monthly dumps would otherwise be held in memory as a whole. This is also the largest frame
+// size the convert subcommand writes.
+pub(crate) const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+// the compressed offset and size of every frame in a zstd file. Returns None if the file isn't
+// zstd, has a single frame, or a frame that doesn't state its decompressed size or decompresses to
+// more than MAX_FRAME_SIZE. Files that can't be walked are left to the stream decoder, which
+// reports the error.
+pub(crate) fn find_frames(file: &mut File) -> Option<Vec<(u64, u64)>> {
+    let frames = walk_frames(file).ok().flatten()?;
+    if frames.len() < 2 {
+        return None;
+    }
+    Some(frames)
+}
+
+fn walk_frames(file: &mut File) -> io::Result<Option<Vec<(u64, u64)>>> {
+    let file_size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let mut input = BufReader::new(file);
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < file_size {
+        let magic = read_u32(&mut input)?;
+        if magic & SKIPPABLE_MASK == SKIPPABLE_MAGIC {
+            let size = read_u32(&mut input)? as u64;
+            input.seek_relative(size as i64)?;
+            offset += 8 + size;
+            continue;
+        }
+        if magic != ZSTD_MAGIC {
+            return Ok(None);
+        }
+
+        let descriptor = read_u8(&mut input)?;
+        let single_segment = descriptor & 0x20 != 0;
+        let has_checksum = descriptor & 0x04 != 0;
+        let dictionary_id_size = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+        let content_size_size = match descriptor >> 6 {
+            0 => single_segment as usize,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        // the frames are held in memory decompressed, so their size has to be known up front
+        if content_size_size == 0 {
+            return Ok(None);
+        }
+        let window_size = !single_segment as i64;
+        input.seek_relative(window_size + dictionary_id_size)?;
+        let mut content_size = [0; 8];
+        input.read_exact(&mut content_size[..content_size_size])?;
+        let content_size = match content_size_size {
+            2 => u64::from_le_bytes(content_size) + 256,
+            _ => u64::from_le_bytes(content_size),
+        };
+        if content_size > MAX_FRAME_SIZE {
+            return Ok(None);
+        }
+        let mut size = 5 + (window_size + dictionary_id_size) as u64 + content_size_size as u64;
+
+        loop {
+            let mut header = [0; 3];
+            input.read_exact(&mut header)?;
+            let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+            let last_block = header & 1 != 0;
+            let block_size = match (header >> 1) & 3 {
+                // RLE blocks store a single byte that is repeated
+                1 => 1,
+                3 => return Ok(None),
+                _ => (header >> 3) as u64,
+            };
+            input.seek_relative(block_size as i64)?;
+            size += 3 + block_size;
+            if last_block {
+                break;
+            }
+        }
+        if has_checksum {
+            input.seek_relative(4)?;
+            size += 4;
+        }
+        frames.push((offset, size));
+        offset += size;
+    }
+    Ok(Some(frames))
+}
+
+// frames are only decompressed up to MAX_FRAME_SIZE, in case the size stated in a frame header or
+// seek table is wrong
+fn decompress_frame(frame: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoder = zstd::Decoder::with_buffer(frame)?;
+    decoder.window_log_max(31)?;
+    let mut data = Vec::new();
+    decoder.take(MAX_FRAME_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "a zstd frame decompresses to more than {} bytes",
+                MAX_FRAME_SIZE
+            ),
+        ));
+    }
+    Ok(data)
+}
+
+// reads the frames of a zstd file in order, decompressing as many frames at once as there are
+// threads in the pool
+pub(crate) struct FrameReader {
+    file: CountingReader<File>,
+    frames: Vec<(u64, u64)>,
+    // the frames decompressed ahead of time, starting with frame `next`
+    ahead: VecDeque<Vec<u8>>,
+    next: usize,
+    // the current frame and the position in it
+    data: Vec<u8>,
+    position: usize,
+    // bytes to skip at the start of the next frame after a seek
+    skip_in_frame: usize,
+}
+
+impl FrameReader {
+    pub(crate) fn new(file: CountingReader<File>, frames: Vec<(u64, u64)>) -> Self {
+        Self {
+            file,
+            frames,
+            ahead: VecDeque::new(),
+            next: 0,
+            data: Vec::new(),
+            position: 0,
+            skip_in_frame: 0,
+        }
+    }
+
+    // the bytes left in the current frame
+    pub(crate) fn buffered(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+
+    // continues reading at the given position of a frame. Frames that were already decompressed
+    // are kept if they come after it.
+    pub(crate) fn seek(&mut self, frame: usize, position: usize) {
+        if frame >= self.next && frame < self.next + self.ahead.len() {
+            self.ahead.drain(..frame - self.next);
+        } else {
+            self.ahead.clear();
+        }
+        self.next = frame;
+        self.data.clear();
+        self.position = 0;
+        self.skip_in_frame = position;
+    }
+
+    fn decompress_batch(&mut self) -> io::Result<()> {
+        let end = (self.next + rayon::current_num_threads()).min(self.frames.len());
+        let mut compressed = Vec::with_capacity(end - self.next);
+        for (offset, size) in &self.frames[self.next..end] {
+            self.file.seek(SeekFrom::Start(*offset))?;
+            let mut frame = vec![0; *size as usize];
+            self.file.read_exact(&mut frame)?;
+            compressed.push(frame);
+        }
+        let decompressed: Vec<Vec<u8>> = compressed
+            .par_iter()
+            .map(|frame| decompress_frame(frame))
+            .collect::<io::Result<_>>()?;
+        self.ahead.extend(decompressed);
+        Ok(())
+    }
+}
+
+impl Read for FrameReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for FrameReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // lines that continue in the next frame are stitched together by the caller reading on
+        while self.position >= self.data.len() {
+            if self.ahead.is_empty() {
+                if self.next >= self.frames.len() {
+                    return Ok(&[]);
+                }
+                self.decompress_batch()?;
+            }
+            self.data = self.ahead.pop_front().unwrap_or_default();
+            self.next += 1;
+            self.position = std::mem::take(&mut self.skip_in_frame).min(self.data.len());
+        }
+        Ok(&self.data[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position += amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    // a file in the temporary directory that is removed again when the test ends
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let file_name = format!("reddit-search-{}-{}", std::process::id(), name);
+            Self(std::env::temp_dir().join(file_name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    // a frame that states its decompressed size in the header
+    fn frame(data: &[u8]) -> Vec<u8> {
+        zstd::bulk::compress(data, 3).unwrap()
+    }
+
+    // a frame written by a streaming encoder, which doesn't know the size up front
+    fn streamed_frame(data: &[u8]) -> Vec<u8> {
+        let mut encoder = zstd::Encoder::new(Vec::new(), 3).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn frames_of(name: &str, frames: &[Vec<u8>]) -> (TempFile, Option<Vec<(u64, u64)>>) {
+        let path = TempFile::new(name);
+        std::fs::write(&path.0, frames.concat()).unwrap();
+        let frames = find_frames(&mut File::open(&path.0).unwrap());
+        (path, frames)
+    }
+
+    #[test]
+    fn finds_and_reads_frames() {
+        let lines: Vec<String> = (0..3)
+            .map(|i| format!("{{\"id\":{}}}\n", i).repeat(1000))
+            .collect();
+        // skippable frames between the frames are skipped
+        let mut skippable = (SKIPPABLE_MAGIC + 3).to_le_bytes().to_vec();
+        skippable.extend(4u32.to_le_bytes());
+        skippable.extend([0; 4]);
+        let compressed = [
+            frame(lines[0].as_bytes()),
+            skippable,
+            frame(lines[1].as_bytes()),
+            frame(lines[2].as_bytes()),
+        ];
+        let (path, frames) = frames_of("frames.zst", &compressed);
+        let frames = frames.unwrap();
+        let sizes: Vec<u64> = compressed.iter().map(|frame| frame.len() as u64).collect();
+        assert_eq!(
+            frames,
+            [
+                (0, sizes[0]),
+                (sizes[0] + sizes[1], sizes[2]),
+                (sizes[0] + sizes[1] + sizes[2], sizes[3])
+            ]
+        );
+
+        let file = CountingReader::new(File::open(&path.0).unwrap(), Arc::new(AtomicU64::new(0)));
+        let mut data = String::new();
+        FrameReader::new(file, frames)
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, lines.concat());
+    }
+
+    #[test]
+    fn leaves_single_frames_to_the_stream_decoder() {
+        assert!(frames_of("single.zst", &[frame(b"{}\n")]).1.is_none());
+        assert!(frames_of("not-zstd.zst", &[b"{}\n{}\n".to_vec()])
+            .1
+            .is_none());
+    }
+
+    #[test]
+    fn leaves_frames_without_a_content_size_to_the_stream_decoder() {
+        let frames = [streamed_frame(b"{}\n"), streamed_frame(b"{}\n")];
+        assert!(frames_of("streamed.zst", &frames).1.is_none());
+    }
+
+    #[test]
+    fn leaves_frames_decompressing_to_more_than_the_maximum_to_the_stream_decoder() {
+        // highly compressible frames are far smaller than the maximum when compressed
+        let zeros = vec![0; MAX_FRAME_SIZE as usize + 1];
+        let large = frame(&zeros);
+        assert!((large.len() as u64) < MAX_FRAME_SIZE / 1000);
+        assert!(frames_of("large.zst", &[frame(b"{}\n"), large]).1.is_none());
+
+        let largest = frame(&zeros[1..]);
+        assert!(frames_of("largest.zst", &[frame(b"{}\n"), largest])
+            .1
+            .is_some());
+    }
+}
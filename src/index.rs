@@ -0,0 +1,487 @@
+use crate::inputs::{file_stamp, open_input};
+use crate::json::{find_value, value_text};
+use crate::query::Query;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Take, Write};
+use std::path::{Path, PathBuf};
+
+// a block index records, for every block of lines in a dump, where the block starts in the
+// decompressed data and Bloom filters of the subreddits and authors in it. Searches for exact
+// subreddits or authors use it to skip blocks that can't contain a match. The index is stored
+// next to the dump as <dump>.rsidx and is ignored once the dump changes.
+
+const INDEX_EXTENSION: &str = "rsidx";
+// the version was raised when the hash of the filters changed
+const MAGIC: &[u8; 8] = b"RSIDX002";
+// the fields the index has filters for, values are stored lowercased
+const INDEXED_FIELDS: [&str; 2] = ["subreddit", "author"];
+// about 1% false positives with 7 hash functions
+const BITS_PER_VALUE: usize = 10;
+const HASH_COUNT: u64 = 7;
+
+pub(crate) fn index_path(input: &Path) -> PathBuf {
+    let mut path = input.as_os_str().to_owned();
+    path.push(".");
+    path.push(INDEX_EXTENSION);
+    PathBuf::from(path)
+}
+
+// 64 bit FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// the murmur3 finalizer. FNV alone leaves values that only differ in their last characters close
+// together in the low bits, which the filter positions are taken from.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    fn with_capacity(values: usize) -> Self {
+        let words = (values.max(1) * BITS_PER_VALUE).div_ceil(64);
+        Self {
+            bits: vec![0; words],
+        }
+    }
+
+    // the bit positions of a value, using double hashing on a single FNV hash
+    fn positions(&self, value: &str) -> impl Iterator<Item = usize> {
+        let hash = mix(fnv1a(value.as_bytes()));
+        let (first, second) = (hash, hash.rotate_left(32) | 1);
+        let bit_count = self.bits.len() as u64 * 64;
+        (0..HASH_COUNT)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bit_count) as usize)
+    }
+
+    fn insert(&mut self, value: &str) {
+        for position in self.positions(value).collect::<Vec<usize>>() {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    fn may_contain(&self, value: &str) -> bool {
+        self.positions(value)
+            .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        output.write_all(&(self.bits.len() as u64).to_le_bytes())?;
+        for word in &self.bits {
+            output.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // the size is checked against the rest of the index before allocating, a damaged index
+    // could claim any size
+    fn read(input: &mut Take<impl Read>) -> io::Result<Self> {
+        let words = read_u64(input)?;
+        if words == 0 || words > input.limit() / 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("a filter of {} words doesn't fit in the index", words),
+            ));
+        }
+        let mut bits = Vec::with_capacity(words as usize);
+        for _ in 0..words {
+            bits.push(read_u64(input)?);
+        }
+        Ok(Self { bits })
+    }
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+struct Block {
+    // where the block starts in the decompressed data
+    offset: u64,
+    lines: u64,
+    // one filter per indexed field
+    filters: Vec<BloomFilter>,
+}
+
+pub(crate) struct BlockIndex {
+    // the size and modification time of the dump, used to detect a stale index
+    file_size: u64,
+    modified: u64,
+    decompressed_size: u64,
+    blocks: Vec<Block>,
+}
+
+impl BlockIndex {
+    // reads the whole dump once and records a block every `block_lines` lines
+    pub(crate) fn build(input: &Path, block_lines: u64) -> io::Result<Self> {
+        let (file_size, modified) = file_stamp(input)?;
+        let mut input_stream = open_input(input)?;
+        let mut blocks = Vec::new();
+        let mut values: Vec<HashSet<String>> = vec![HashSet::new(); INDEXED_FIELDS.len()];
+        let mut offset = 0;
+        let mut block_offset = 0;
+        let mut block_line_count = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = input_stream.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            offset += read as u64;
+            block_line_count += 1;
+            for (field, values) in INDEXED_FIELDS.iter().zip(values.iter_mut()) {
+                if let Some(raw) = find_value(&line, field).filter(|raw| *raw != "null") {
+                    values.insert(value_text(raw).to_lowercase());
+                }
+            }
+            if block_line_count == block_lines {
+                blocks.push(Block::new(block_offset, block_line_count, &mut values));
+                block_offset = offset;
+                block_line_count = 0;
+            }
+        }
+        if block_line_count > 0 {
+            blocks.push(Block::new(block_offset, block_line_count, &mut values));
+        }
+        Ok(Self {
+            file_size,
+            modified,
+            decompressed_size: offset,
+            blocks,
+        })
+    }
+
+    pub(crate) fn lines(&self) -> u64 {
+        self.blocks.iter().map(|block| block.lines).sum()
+    }
+
+    pub(crate) fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> io::Result<()> {
+        let mut output = BufWriter::new(File::create(path)?);
+        output.write_all(MAGIC)?;
+        for value in [
+            self.file_size,
+            self.modified,
+            self.decompressed_size,
+            self.blocks.len() as u64,
+        ] {
+            output.write_all(&value.to_le_bytes())?;
+        }
+        for block in &self.blocks {
+            output.write_all(&block.offset.to_le_bytes())?;
+            output.write_all(&block.lines.to_le_bytes())?;
+            for filter in &block.filters {
+                filter.write(&mut output)?;
+            }
+        }
+        output.flush()
+    }
+
+    // reads the index of a dump if there is one. Returns None if there is no index, or if the
+    // dump has changed since it was indexed.
+    pub(crate) fn open(input: &Path) -> io::Result<Option<Self>> {
+        let path = index_path(input);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let file = File::open(&path)?;
+        let length = file.metadata()?.len();
+        let mut index = BufReader::new(file).take(length);
+        let mut magic = [0; 8];
+        index.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is not a reddit-search index or was written by an older version. Run the index command again to rebuild it.",
+                    path.display()
+                ),
+            ));
+        }
+        let file_size = read_u64(&mut index)?;
+        let modified = read_u64(&mut index)?;
+        if (file_size, modified) != file_stamp(input)? {
+            status!(
+                "Warning: {} was modified after it was indexed, the index is not used. Run the index command again to update it.",
+                input.display()
+            );
+            return Ok(None);
+        }
+        let decompressed_size = read_u64(&mut index)?;
+        let blocks = read_blocks(&mut index, decompressed_size).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The index {} is damaged ({}). Run the index command again to rebuild it.",
+                    path.display(),
+                    e
+                ),
+            )
+        })?;
+        Ok(Some(Self {
+            file_size,
+            modified,
+            decompressed_size,
+            blocks,
+        }))
+    }
+
+    // the blocks as the reader sees them, and whether each of them may contain a match for the
+    // query
+    pub(crate) fn plan(&self, query: &Query) -> Vec<PlannedBlock> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| {
+                let end = self
+                    .blocks
+                    .get(i + 1)
+                    .map_or(self.decompressed_size, |next| next.offset);
+                PlannedBlock {
+                    size: end - block.offset,
+                    lines: block.lines,
+                    search: query.may_match(&|field, value| block.may_contain(field, value)),
+                }
+            })
+            .collect()
+    }
+}
+
+// the blocks of an index. Blocks have to start in order within the decompressed data, otherwise
+// the reader couldn't skip to them.
+fn read_blocks(index: &mut Take<impl Read>, decompressed_size: u64) -> io::Result<Vec<Block>> {
+    let block_count = read_u64(index)?;
+    // every block holds at least its offset, its line count and a filter size per field
+    let block_size = 16 + 8 * INDEXED_FIELDS.len() as u64;
+    if block_count > index.limit() / block_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} blocks don't fit in the index", block_count),
+        ));
+    }
+    let mut blocks: Vec<Block> = Vec::with_capacity(block_count as usize);
+    for _ in 0..block_count {
+        let offset = read_u64(index)?;
+        let previous = blocks.last().map_or(0, |block| block.offset);
+        if offset < previous || offset > decompressed_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("a block starts at {}, outside of the dump", offset),
+            ));
+        }
+        let lines = read_u64(index)?;
+        let mut filters = Vec::with_capacity(INDEXED_FIELDS.len());
+        for _ in INDEXED_FIELDS {
+            filters.push(BloomFilter::read(index)?);
+        }
+        blocks.push(Block {
+            offset,
+            lines,
+            filters,
+        });
+    }
+    Ok(blocks)
+}
+
+// a block of the decompressed input, either searched or skipped by the reader
+pub(crate) struct PlannedBlock {
+    pub(crate) size: u64,
+    pub(crate) lines: u64,
+    pub(crate) search: bool,
+}
+
+impl Block {
+    fn new(offset: u64, lines: u64, values: &mut [HashSet<String>]) -> Self {
+        let filters = values
+            .iter_mut()
+            .map(|values| {
+                let mut filter = BloomFilter::with_capacity(values.len());
+                for value in values.drain() {
+                    filter.insert(&value);
+                }
+                filter
+            })
+            .collect();
+        Self {
+            offset,
+            lines,
+            filters,
+        }
+    }
+
+    // None if the field is not indexed
+    fn may_contain(&self, field: &str, value: &str) -> Option<bool> {
+        let position = INDEXED_FIELDS
+            .iter()
+            .position(|indexed| *indexed == field)?;
+        Some(self.filters[position].may_contain(&value.to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // a file in the temporary directory that is removed again when the test ends
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let file_name = format!("reddit-search-{}-{}", std::process::id(), name);
+            Self(std::env::temp_dir().join(file_name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(index_path(&self.0));
+        }
+    }
+
+    fn read_filter(bytes: &[u8]) -> io::Result<BloomFilter> {
+        BloomFilter::read(&mut Cursor::new(bytes).take(bytes.len() as u64))
+    }
+
+    #[test]
+    fn bloom_filter_round_trip() {
+        let mut filter = BloomFilter::with_capacity(100);
+        let values: Vec<String> = (0..100).map(|i| format!("user_{}", i)).collect();
+        for value in &values {
+            filter.insert(value);
+        }
+        let mut bytes = Vec::new();
+        filter.write(&mut bytes).unwrap();
+        let read = read_filter(&bytes).unwrap();
+        assert_eq!(read.bits, filter.bits);
+        assert!(values.iter().all(|value| read.may_contain(value)));
+        let false_positives = (0..1000)
+            .filter(|i| read.may_contain(&format!("other_{}", i)))
+            .count();
+        assert!(false_positives < 20, "{}", false_positives);
+    }
+
+    #[test]
+    fn rejects_filters_larger_than_the_index() {
+        let mut bytes = Vec::new();
+        BloomFilter::with_capacity(10).write(&mut bytes).unwrap();
+        assert!(read_filter(&bytes[..bytes.len() - 1]).is_err());
+        let mut huge = u64::MAX.to_le_bytes().to_vec();
+        huge.extend([0; 64]);
+        assert!(read_filter(&huge).is_err());
+        assert!(read_filter(&0u64.to_le_bytes()).is_err());
+    }
+
+    // six lines in blocks of two, each block from another subreddit
+    fn indexed_dump(name: &str) -> (TempFile, BlockIndex) {
+        let dump = TempFile::new(name);
+        let lines: String = ["a", "a", "b", "b", "c", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, subreddit)| {
+                format!(
+                    "{{\"id\":{},\"subreddit\":\"{}\",\"author\":\"u{}\"}}\n",
+                    i, subreddit, i
+                )
+            })
+            .collect();
+        std::fs::write(&dump.0, lines).unwrap();
+        BlockIndex::build(&dump.0, 2)
+            .unwrap()
+            .write(&index_path(&dump.0))
+            .unwrap();
+        let index = BlockIndex::open(&dump.0).unwrap().unwrap();
+        (dump, index)
+    }
+
+    fn searched(index: &BlockIndex, query: &str) -> Vec<bool> {
+        let query = Query::parse(query).unwrap();
+        index
+            .plan(&query)
+            .iter()
+            .map(|block| block.search)
+            .collect()
+    }
+
+    #[test]
+    fn plans_which_blocks_to_search() {
+        let (dump, index) = indexed_dump("plan.ndjson");
+        assert_eq!(index.block_count(), 3);
+        assert_eq!(index.lines(), 6);
+        let plan = index.plan(&Query::parse("subreddit:^b$").unwrap());
+        let sizes: u64 = plan.iter().map(|block| block.size).sum();
+        assert_eq!(sizes, std::fs::metadata(&dump.0).unwrap().len());
+        assert!(plan.iter().all(|block| block.lines == 2));
+
+        assert_eq!(searched(&index, "subreddit:^B$"), [false, true, false]);
+        assert_eq!(searched(&index, "subreddit:^(a|c)$"), [true, false, true]);
+        assert_eq!(searched(&index, "author:^u3$"), [false, true, false]);
+        assert_eq!(
+            searched(&index, "subreddit:^a$ AND author:^u3$"),
+            [false, false, false]
+        );
+        assert_eq!(
+            searched(&index, "subreddit:^a$ OR author:^u5$"),
+            [true, false, true]
+        );
+        // filters that aren't exact values, NOT and fields without filters search every block
+        assert_eq!(searched(&index, "subreddit:^b"), [true, true, true]);
+        assert_eq!(searched(&index, "NOT subreddit:^b$"), [true, true, true]);
+        assert_eq!(searched(&index, "id:^3$"), [true, true, true]);
+    }
+
+    #[test]
+    fn ignores_the_index_of_a_changed_dump() {
+        let (dump, _) = indexed_dump("changed.ndjson");
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&dump.0)
+            .unwrap();
+        writeln!(file, "{{\"id\":6,\"subreddit\":\"d\"}}").unwrap();
+        assert!(BlockIndex::open(&dump.0).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_damaged_indexes() {
+        let (dump, _) = indexed_dump("damaged.ndjson");
+        let path = index_path(&dump.0);
+        let bytes = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        let error = BlockIndex::open(&dump.0).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // the block count follows the magic, size, modification time and decompressed size
+        let mut huge_count = bytes.clone();
+        huge_count[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, huge_count).unwrap();
+        assert!(BlockIndex::open(&dump.0).is_err());
+
+        let mut wrong_offset = bytes;
+        wrong_offset[40..48].copy_from_slice(&1000u64.to_le_bytes());
+        std::fs::write(&path, wrong_offset).unwrap();
+        assert!(BlockIndex::open(&dump.0).is_err());
+    }
+}
@@ -0,0 +1,413 @@
+use crate::constants::{
+    COMMENT_COLUMNS, COMMENT_ONLY_FIELDS, SUBMISSION_COLUMNS, SUBMISSION_ONLY_FIELDS,
+};
+use crate::frames::{find_frames, FrameReader};
+use crate::seekable::{SeekTable, SeekableReader};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+// the dumps are distributed as zstd compressed files, directories are searched for these only
+const DUMP_EXTENSION: &str = "zst";
+// the input name that reads from stdin
+pub(crate) const STDIN: &str = "-";
+
+// magic bytes at the start of the supported compressed formats, anything else is read as plain
+// newline delimited JSON
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const XZ_MAGIC: &[u8] = &[0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+
+// turns the --input values into a list of files. Each value can be a file, a directory (all .zst
+// files directly inside it are used) or a glob pattern like /data/reddit/RC_2016-*.zst. Files
+// from directories and globs are sorted by name, so monthly dumps are searched in order.
+pub(crate) fn resolve_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for input in inputs {
+        let path = PathBuf::from(input);
+        if input == STDIN {
+            files.push(path);
+        } else if path.is_dir() {
+            let mut dir_files: Vec<PathBuf> = fs::read_dir(&path)
+                .map_err(|e| format!("Failed to read directory {}: {}", input, e))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| {
+                    file.is_file()
+                        && file.extension().and_then(|ext| ext.to_str()) == Some(DUMP_EXTENSION)
+                })
+                .collect();
+            if dir_files.is_empty() {
+                return Err(format!(
+                    "Input directory {} contains no .{} files.",
+                    input, DUMP_EXTENSION
+                ));
+            }
+            dir_files.sort();
+            files.extend(dir_files);
+        } else if path.is_file() {
+            files.push(path);
+        } else if input.contains(['*', '?', '[']) {
+            let mut matched: Vec<PathBuf> = glob::glob(input)
+                .map_err(|e| format!("Invalid glob pattern {}: {}", input, e))?
+                .filter_map(Result::ok)
+                .filter(|file| file.is_file())
+                .collect();
+            if matched.is_empty() {
+                return Err(format!("No input files match {}.", input));
+            }
+            matched.sort();
+            files.extend(matched);
+        } else {
+            return Err(format!("Input file {} does not exist.", input));
+        }
+    }
+    // the same file can be matched by more than one value, only search it once
+    let mut seen = std::collections::HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+    Ok(files)
+}
+
+// an opened input. Seekable zstd files (see seekable.rs) can skip ahead without decompressing
+// what is skipped, all other inputs have to be read through. Zstd files with many frames are
+// decompressed in parallel (see frames.rs).
+pub(crate) enum Input {
+    Stream(Box<dyn BufRead + Send>),
+    Frames(FrameReader),
+    Seekable(SeekableReader),
+}
+
+impl Input {
+    // skips the given number of decompressed bytes
+    pub(crate) fn skip(&mut self, bytes: u64) -> io::Result<()> {
+        if let Input::Seekable(reader) = self {
+            reader.skip(bytes);
+        } else {
+            io::copy(&mut Read::take(self, bytes), &mut io::sink())?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Stream(stream) => stream.read(buf),
+            Input::Frames(reader) => reader.read(buf),
+            Input::Seekable(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl BufRead for Input {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Input::Stream(stream) => stream.fill_buf(),
+            Input::Frames(reader) => reader.fill_buf(),
+            Input::Seekable(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        match self {
+            Input::Stream(stream) => stream.consume(amount),
+            Input::Frames(reader) => reader.consume(amount),
+            Input::Seekable(reader) => reader.consume(amount),
+        }
+    }
+}
+
+// counts the bytes read from a file and follows seeks, so its position is the number of
+// compressed bytes a search has consumed. The count is shared with the progress bars.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    position: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R, position: Arc<AtomicU64>) -> Self {
+        Self { inner, position }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = self.inner.seek(position)?;
+        self.position.store(position, Ordering::Relaxed);
+        Ok(position)
+    }
+}
+
+pub(crate) fn open_input(path: &Path) -> io::Result<Input> {
+    open_counted_input(path, Arc::new(AtomicU64::new(0)))
+}
+
+// opens an input file, or stdin for `-`, and decompresses it according to its first bytes. The
+// monthly dumps are zstd compressed, older pushshift files were distributed as gzip, bzip2 or xz
+// and our own outputs may not be compressed at all. The compressed bytes read from a file are
+// counted in `position`, stdin isn't counted.
+pub(crate) fn open_counted_input(path: &Path, position: Arc<AtomicU64>) -> io::Result<Input> {
+    let mut input: Box<dyn Read + Send> = if path.as_os_str() == STDIN {
+        Box::new(io::stdin())
+    } else {
+        let mut file = File::open(path)?;
+        let table = SeekTable::read(&mut file)?;
+        let frames = match table {
+            Some(_) => None,
+            None => find_frames(&mut file),
+        };
+        file.seek(SeekFrom::Start(0))?;
+        let file = CountingReader::new(file, position);
+        match (table, frames) {
+            // seekable files with frames larger than MAX_FRAME_SIZE are decompressed as a stream
+            (Some(table), _) if table.frames_fit() => {
+                return Ok(Input::Seekable(SeekableReader::new(file, table)));
+            }
+            (None, Some(frames)) => return Ok(Input::Frames(FrameReader::new(file, frames))),
+            _ => Box::new(file),
+        }
+    };
+    // a pipe may return fewer bytes per read than the magic is long
+    let mut magic = Vec::with_capacity(XZ_MAGIC.len());
+    (&mut input)
+        .take(XZ_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let input = io::Cursor::new(magic.clone()).chain(input);
+
+    Ok(Input::Stream(if magic.starts_with(ZSTD_MAGIC) {
+        let mut decoder = zstd::Decoder::new(input)?;
+        decoder.window_log_max(31)?;
+        Box::new(BufReader::new(decoder))
+    } else if magic.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(MultiGzDecoder::new(input)))
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Box::new(BufReader::new(MultiBzDecoder::new(input)))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Box::new(BufReader::new(xz2::read::XzDecoder::new_multi_decoder(
+            input,
+        )))
+    } else {
+        Box::new(BufReader::new(input))
+    }))
+}
+
+// the size and modification time (in seconds) of a file
+pub(crate) fn file_stamp(input: &Path) -> io::Result<(u64, u64)> {
+    let metadata = input.metadata()?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+// the file name without its directory, the line count cache keys on it with the size and time
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+// the two kinds of monthly dumps, comments are named RC_YYYY-MM.zst and submissions RS_YYYY-MM.zst
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum DumpKind {
+    Comments,
+    Submissions,
+}
+
+impl DumpKind {
+    // detects the kind of dump from names like RC_2016-09.zst or RS_2016-09.zst
+    pub(crate) fn from_file_name(file_name: &str) -> Option<Self> {
+        if file_name.starts_with("RC_") {
+            Some(DumpKind::Comments)
+        } else if file_name.starts_with("RS_") {
+            Some(DumpKind::Submissions)
+        } else {
+            None
+        }
+    }
+
+    // fields that exist in the other kind of dump but never in this one
+    pub(crate) fn foreign_fields(&self) -> &'static [&'static str] {
+        match self {
+            DumpKind::Comments => &SUBMISSION_ONLY_FIELDS,
+            DumpKind::Submissions => &COMMENT_ONLY_FIELDS,
+        }
+    }
+
+    pub(crate) fn default_columns(&self) -> &'static [&'static str] {
+        match self {
+            DumpKind::Comments => &COMMENT_COLUMNS,
+            DumpKind::Submissions => &SUBMISSION_COLUMNS,
+        }
+    }
+
+    pub(crate) fn prefix(&self) -> &'static str {
+        match self {
+            DumpKind::Comments => "RC_",
+            DumpKind::Submissions => "RS_",
+        }
+    }
+
+    // also the name of the subdirectory the torrent puts these dumps in
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            DumpKind::Comments => "comments",
+            DumpKind::Submissions => "submissions",
+        }
+    }
+}
+
+impl FromStr for DumpKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "comments" => Ok(DumpKind::Comments),
+            "submissions" => Ok(DumpKind::Submissions),
+            _ => Err(format!(
+                "Unknown kind {}, must be comments or submissions",
+                kind
+            )),
+        }
+    }
+}
+
+// parses a month in the format YYYY-MM
+fn parse_month(month: &str) -> Option<(i32, u32)> {
+    let (year, month) = month.split_once('-')?;
+    if year.len() != 4 || month.len() != 2 {
+        return None;
+    }
+    let year = year.parse::<i32>().ok()?;
+    let month = month.parse::<u32>().ok()?;
+    if (1..=12).contains(&month) {
+        Some((year, month))
+    } else {
+        None
+    }
+}
+
+fn next_month((year, month): (i32, u32)) -> (i32, u32) {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+// finds the monthly dumps of the given kind between `from` and `to` (both inclusive, YYYY-MM) in
+// the dump directory. The files may be directly inside the directory or in a comments or
+// submissions subdirectory, as laid out by the torrent. If `from` or `to` is not given, the first
+// or last month found in the directory is used. Missing months are reported as a warning.
+pub(crate) fn resolve_dump_range(
+    dump_dir: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    kind: DumpKind,
+) -> Result<Vec<PathBuf>, String> {
+    let base = PathBuf::from(dump_dir);
+    if !base.is_dir() {
+        return Err(format!("Dump directory {} does not exist.", dump_dir));
+    }
+    let directories = [base.join(kind.name()), base];
+
+    // collect the months that are available so open ended ranges can be resolved
+    let mut available: Vec<(i32, u32)> = Vec::new();
+    for directory in &directories {
+        let Ok(entries) = fs::read_dir(directory) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let month = name
+                .strip_prefix(kind.prefix())
+                .and_then(|rest| rest.strip_suffix(".zst"))
+                .and_then(parse_month);
+            if let Some(month) = month {
+                available.push(month);
+            }
+        }
+    }
+    available.sort();
+    if available.is_empty() {
+        return Err(format!(
+            "No {}YYYY-MM.zst files found in {}.",
+            kind.prefix(),
+            dump_dir
+        ));
+    }
+
+    let from = match from {
+        Some(from) => parse_month(from)
+            .ok_or_else(|| format!("Month {} is not in the format YYYY-MM", from))?,
+        None => available[0],
+    };
+    let to = match to {
+        Some(to) => {
+            parse_month(to).ok_or_else(|| format!("Month {} is not in the format YYYY-MM", to))?
+        }
+        None => available[available.len() - 1],
+    };
+    if from > to {
+        return Err(format!(
+            "--from {}-{:02} is after --to {}-{:02}",
+            from.0, from.1, to.0, to.1
+        ));
+    }
+
+    let mut files = Vec::new();
+    let mut missing = Vec::new();
+    let mut month = from;
+    while month <= to {
+        let name = format!("{}{}-{:02}.zst", kind.prefix(), month.0, month.1);
+        match directories
+            .iter()
+            .map(|directory| directory.join(&name))
+            .find(|file| file.is_file())
+        {
+            Some(file) => files.push(file),
+            None => missing.push(format!("{}-{:02}", month.0, month.1)),
+        }
+        month = next_month(month);
+    }
+
+    if files.is_empty() {
+        return Err(format!(
+            "No {} dumps found in {} between {}-{:02} and {}-{:02}.",
+            kind.name(),
+            dump_dir,
+            from.0,
+            from.1,
+            to.0,
+            to.1
+        ));
+    }
+    if !missing.is_empty() {
+        status!(
+            "Warning: No {} dumps found in {} for {} month(s): {}",
+            kind.name(),
+            dump_dir,
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    Ok(files)
+}
@@ -0,0 +1,282 @@
+use std::borrow::Cow;
+
+// a minimal JSON scanner used to pull single values out of a line without parsing all of it.
+// It only walks over the keys of the objects on the requested path and skips every other value,
+// so text inside strings or nested objects can never be mistaken for a key.
+
+// returns the raw JSON text of the value at the given path, e.g. "subreddit" or
+// "media.oembed.title" for nested objects. Only the top level object is searched unless the path
+// contains dots.
+pub(crate) fn find_value<'a>(line: &'a str, path: &str) -> Option<&'a str> {
+    let bytes = line.as_bytes();
+    let mut start = skip_whitespace(bytes, 0);
+    let mut end = start;
+    for key in path.split('.') {
+        let (value_start, value_end) = find_key(bytes, start, key)?;
+        start = value_start;
+        end = value_end;
+    }
+    Some(&line[start..end])
+}
+
+// returns the value at the given path as text. Strings are unescaped, all other values (numbers,
+// booleans, null, objects and arrays) are returned as they appear in the line.
+pub(crate) fn field_text<'a>(line: &'a str, path: &str) -> Option<Cow<'a, str>> {
+    find_value(line, path).map(value_text)
+}
+
+// turns the raw JSON text of a value into text, see field_text
+pub(crate) fn value_text(raw: &str) -> Cow<'_, str> {
+    match raw.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(string) => unescape(string),
+        None => Cow::Borrowed(raw),
+    }
+}
+
+// looks up a key in the object starting at `start` and returns the bounds of its value
+fn find_key(bytes: &[u8], start: usize, key: &str) -> Option<(usize, usize)> {
+    if bytes.get(start) != Some(&b'{') {
+        return None;
+    }
+    let mut i = skip_whitespace(bytes, start + 1);
+    if bytes.get(i) == Some(&b'}') {
+        return None;
+    }
+    loop {
+        if bytes.get(i) != Some(&b'"') {
+            return None;
+        }
+        let key_end = skip_string(bytes, i)?;
+        let is_match = &bytes[i + 1..key_end - 1] == key.as_bytes();
+        i = skip_whitespace(bytes, key_end);
+        if bytes.get(i) != Some(&b':') {
+            return None;
+        }
+        let value_start = skip_whitespace(bytes, i + 1);
+        let value_end = skip_value(bytes, value_start)?;
+        if is_match {
+            return Some((value_start, value_end));
+        }
+        i = skip_whitespace(bytes, value_end);
+        match bytes.get(i) {
+            Some(b',') => i = skip_whitespace(bytes, i + 1),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+// `i` points at the opening quote, returns the index after the closing quote
+fn skip_string(bytes: &[u8], mut i: usize) -> Option<usize> {
+    i += 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => return Some(i + 1),
+            b'\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+// returns the index after the value starting at `i`
+fn skip_value(bytes: &[u8], i: usize) -> Option<usize> {
+    match bytes.get(i)? {
+        b'"' => skip_string(bytes, i),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut j = i;
+            while j < bytes.len() {
+                match bytes[j] {
+                    b'"' => {
+                        j = skip_string(bytes, j)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(j + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                j += 1;
+            }
+            None
+        }
+        _ => {
+            let mut j = i;
+            while j < bytes.len()
+                && !matches!(bytes[j], b',' | b'}' | b']')
+                && !bytes[j].is_ascii_whitespace()
+            {
+                j += 1;
+            }
+            if j == i {
+                None
+            } else {
+                Some(j)
+            }
+        }
+    }
+}
+
+// resolves JSON escape sequences, only allocating if the string actually contains any
+pub(crate) fn unescape(raw: &str) -> Cow<'_, str> {
+    if !raw.contains('\\') {
+        return Cow::Borrowed(raw);
+    }
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('b') => result.push('\u{8}'),
+            Some('f') => result.push('\u{c}'),
+            Some('u') => {
+                let code = read_hex(&mut chars);
+                // characters outside the BMP are encoded as a surrogate pair, a surrogate without
+                // its other half becomes a replacement character
+                let low = chars
+                    .as_str()
+                    .strip_prefix("\\u")
+                    .and_then(|rest| rest.get(..4))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .filter(|low| (0xDC00..0xE000).contains(low));
+                let code = match low {
+                    Some(low) if (0xD800..0xDC00).contains(&code) => {
+                        chars.nth(5);
+                        0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+                    }
+                    _ => code,
+                };
+                result.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+    Cow::Owned(result)
+}
+
+// the reverse of unescape, for writing text into a JSON string
+pub(crate) fn escape(text: &str) -> Cow<'_, str> {
+    if !text
+        .chars()
+        .any(|c| c == '"' || c == '\\' || c.is_ascii_control())
+    {
+        return Cow::Borrowed(text);
+    }
+    let mut result = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            c if c.is_ascii_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    Cow::Owned(result)
+}
+
+fn read_hex(chars: &mut std::str::Chars) -> u32 {
+    let hex: String = chars.take(4).collect();
+    if hex.len() != 4 {
+        return 0xFFFD;
+    }
+    u32::from_str_radix(&hex, 16).unwrap_or(0xFFFD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = r#"{"id":"x1", "body" : "say \"author\":\"bob\" {[", "media":{"type":"youtube.com","oembed":{"title":"a \\ b"}},"edited":false,"score":-3,"tags":[{"author":"eve"},"]"],"author":"alice"}"#;
+
+    #[test]
+    fn finds_top_level_values() {
+        assert_eq!(find_value(LINE, "id"), Some("\"x1\""));
+        assert_eq!(find_value(LINE, "score"), Some("-3"));
+        assert_eq!(find_value(LINE, "edited"), Some("false"));
+        // keys inside strings, nested objects and arrays are not top level keys
+        assert_eq!(find_value(LINE, "author"), Some("\"alice\""));
+        assert_eq!(find_value(LINE, "type"), None);
+        assert_eq!(find_value(LINE, "missing"), None);
+        assert_eq!(find_value(LINE, "tags"), Some(r#"[{"author":"eve"},"]"]"#));
+    }
+
+    #[test]
+    fn finds_nested_values() {
+        assert_eq!(find_value(LINE, "media.type"), Some("\"youtube.com\""));
+        assert_eq!(
+            field_text(LINE, "media.oembed.title").as_deref(),
+            Some(r"a \ b")
+        );
+        assert_eq!(find_value(LINE, "media.missing"), None);
+        assert_eq!(find_value(LINE, "score.value"), None);
+    }
+
+    #[test]
+    fn field_text_unescapes_strings_only() {
+        assert_eq!(
+            field_text(LINE, "body").as_deref(),
+            Some(r#"say "author":"bob" {["#)
+        );
+        assert_eq!(field_text(LINE, "score").as_deref(), Some("-3"));
+    }
+
+    #[test]
+    fn malformed_lines_have_no_values() {
+        assert_eq!(find_value("", "id"), None);
+        assert_eq!(find_value("[1,2]", "id"), None);
+        assert_eq!(find_value("{}", "id"), None);
+        assert_eq!(find_value(r#"{"body":"unterminated}"#, "body"), None);
+        assert_eq!(find_value(r#"{"a":1 "id":2}"#, "id"), None);
+    }
+
+    #[test]
+    fn unescapes_escape_sequences() {
+        assert!(matches!(unescape("plain"), Cow::Borrowed("plain")));
+        assert_eq!(unescape(r#"a\"b\\c\/d"#), r#"a"b\c/d"#);
+        assert_eq!(
+            unescape(r"line\nnext\ttab\r\b\f"),
+            "line\nnext\ttab\r\u{8}\u{c}"
+        );
+        assert_eq!(unescape(r"caf\u00e9 \u2603"), "café ☃");
+    }
+
+    #[test]
+    fn unescapes_surrogate_pairs() {
+        assert_eq!(unescape(r"\ud83d\ude00!"), "😀!");
+        assert_eq!(unescape(r"\uD83D\uDE00"), "😀");
+        // surrogates without their other half
+        assert_eq!(unescape(r"\ud83d!"), "\u{fffd}!");
+        assert_eq!(unescape(r"\ud83d\u0041"), "\u{fffd}A");
+        assert_eq!(unescape(r"\ude00"), "\u{fffd}");
+        assert_eq!(unescape(r"\u12"), "\u{fffd}");
+    }
+
+    #[test]
+    fn escapes_text_for_json_strings() {
+        assert!(matches!(escape("plain text"), Cow::Borrowed("plain text")));
+        let text = "say \"hi\"\\ \n\t\u{1}é";
+        assert_eq!(escape(text), r#"say \"hi\"\\ \n\t\u0001é"#);
+        assert_eq!(unescape(&escape(text)), text);
+    }
+}
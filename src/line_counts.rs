@@ -0,0 +1,143 @@
+use crate::inputs::{file_name, file_stamp};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// line counts of the dumps, used for the length of the progress bars. Counts are taken from
+// --linecount and from every search that reads a whole file, and stored in a cache file with one
+// `name;size;modified;lines` line per file. Files are told apart by their name, size and
+// modification time together, so a converted copy with the same name keeps a count of its own and
+// a count is only used while the file is unchanged.
+pub(crate) struct LineCounts {
+    path: Option<PathBuf>,
+    // the line count per file name, size and modification time
+    counts: HashMap<(String, u64, u64), u64>,
+    changed: bool,
+}
+
+// $XDG_CACHE_HOME/reddit-search/line_counts, falling back to ~/.cache and to %LOCALAPPDATA% on
+// Windows
+fn cache_path() -> Option<PathBuf> {
+    let cache_dir = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(cache_dir.join("reddit-search").join("line_counts"))
+}
+
+impl LineCounts {
+    pub(crate) fn load() -> Self {
+        Self::load_from(cache_path())
+    }
+
+    // reads the cache, lines that can't be parsed are ignored
+    fn load_from(path: Option<PathBuf>) -> Self {
+        let mut counts = HashMap::new();
+        if let Some(contents) = path.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+            for line in contents.lines() {
+                let parts: Vec<&str> = line.rsplitn(4, ';').collect();
+                if let [lines, modified, size, name] = parts[..] {
+                    if let (Ok(size), Ok(modified), Ok(lines)) =
+                        (size.parse(), modified.parse(), lines.parse())
+                    {
+                        counts.insert((name.to_string(), size, modified), lines);
+                    }
+                }
+            }
+        }
+        Self {
+            path,
+            counts,
+            changed: false,
+        }
+    }
+
+    // the cached line count of a file, None if it was never counted or has changed since
+    pub(crate) fn get(&self, input: &Path) -> Option<u64> {
+        let (size, modified) = file_stamp(input).ok()?;
+        self.counts
+            .get(&(file_name(input), size, modified))
+            .copied()
+    }
+
+    pub(crate) fn insert(&mut self, input: &Path, lines: u64) {
+        let Ok((size, modified)) = file_stamp(input) else {
+            return;
+        };
+        if self
+            .counts
+            .insert((file_name(input), size, modified), lines)
+            != Some(lines)
+        {
+            self.changed = true;
+        }
+    }
+
+    // writes the cache if a count was added. The counts only speed up later runs, so failing to
+    // write them is a warning.
+    pub(crate) fn save(&mut self) {
+        let Some(path) = self.path.as_ref().filter(|_| self.changed) else {
+            return;
+        };
+        if let Err(e) = self.write(path) {
+            status!(
+                "Warning: Failed to save the line counts to {}: {}",
+                path.display(),
+                e
+            );
+        }
+        self.changed = false;
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut files: Vec<&(String, u64, u64)> = self.counts.keys().collect();
+        files.sort_unstable();
+        let mut contents = String::new();
+        for file in files {
+            let (name, size, modified) = file;
+            let lines = self.counts[file];
+            contents.push_str(&format!("{};{};{};{}\n", name, size, modified, lines));
+        }
+        // write to a temporary file first so a concurrent run never reads half a cache
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_with_the_same_name_keep_their_own_count() {
+        let dir = env::temp_dir().join(format!("reddit-search-{}-counts", std::process::id()));
+        let original = dir.join("RC_2020-01.zst");
+        let converted = dir.join("seekable").join("RC_2020-01.zst");
+        fs::create_dir_all(converted.parent().unwrap()).unwrap();
+        fs::write(&original, "original").unwrap();
+        fs::write(&converted, "converted, with a seek table").unwrap();
+
+        let cache = dir.join("line_counts");
+        let mut counts = LineCounts::load_from(Some(cache.clone()));
+        assert_eq!(counts.get(&original), None);
+        counts.insert(&original, 10);
+        counts.insert(&converted, 20);
+        counts.save();
+
+        let counts = LineCounts::load_from(Some(cache.clone()));
+        assert_eq!(counts.get(&original), Some(10));
+        assert_eq!(counts.get(&converted), Some(20));
+        // a changed file is counted again
+        fs::write(&original, "original, changed").unwrap();
+        assert_eq!(counts.get(&original), None);
+        assert_eq!(counts.get(&converted), Some(20));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
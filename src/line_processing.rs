@@ -1,20 +1,392 @@
+use crate::dates::parse_date;
+use crate::json::field_text;
+use crate::query::Query;
 use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
 
-pub(crate) fn process_line(line: &str, search_strings: &Vec<String>) -> Option<String> {
+// a single filter on one field. The filter is evaluated against the value of the field only,
+// not against the whole line. Nested fields can be addressed with dots, e.g. media.oembed.title
+pub(crate) struct FieldFilter {
+    pub(crate) field: String,
+    pub(crate) matcher: Matcher,
+}
+
+pub(crate) enum Matcher {
+    // <field>:<regex>
+    Regex(Regex),
+    // <field>>=<number>, <field>:<min>..<max> and the other comparisons, all of them are turned
+    // into a pair of bounds. Dates are compared as unix timestamps.
+    Range(Bound<f64>, Bound<f64>),
+}
+
+impl FieldFilter {
+    // parses <field>:<regex>, <field>:<min>..<max> or a comparison like <field>>=<number>
+    pub(crate) fn parse(field: &str) -> Result<Self, String> {
+        let operator_start = match field.find([':', '<', '>', '=']) {
+            Some(pos) if pos > 0 => pos,
+            _ => {
+                return Err(format!(
+                    "Field {} is not in the format <field>:<regex> or <field><operator><number>",
+                    field
+                ))
+            }
+        };
+        let field_key = field[..operator_start].to_lowercase();
+        let rest = &field[operator_start..];
+        // split on the first colon only, the regex itself may contain colons
+        if let Some(pattern) = rest.strip_prefix(':') {
+            // a range needs at least one end, `..` alone is a regex
+            let range = pattern
+                .split_once("..")
+                .filter(|(min, max)| !min.is_empty() || !max.is_empty());
+            if let Some((min, max)) = range {
+                if let (Some(lower), Some(upper)) = (parse_lower(min), parse_upper(max)) {
+                    return Ok(Self {
+                        field: field_key,
+                        matcher: Matcher::Range(lower, upper),
+                    });
+                }
+            }
+            // matching is case insensitive to stay compatible with the old substring search,
+            // use (?-i) in the pattern to make it case sensitive
+            let regex = RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| format!("Invalid regex for field {}: {}", field_key, e))?;
+            return Ok(Self {
+                field: field_key,
+                matcher: Matcher::Regex(regex),
+            });
+        }
+
+        let (operator, value) = [">=", "<=", ">", "<", "="]
+            .iter()
+            .find_map(|operator| rest.strip_prefix(operator).map(|value| (*operator, value)))
+            .unwrap();
+        let (start, end) = parse_endpoint(value).ok_or_else(|| {
+            format!(
+                "Value {} for field {} is not a number or a date",
+                value, field_key
+            )
+        })?;
+        // for dates covering a whole day or month, `start` and `end` differ and the comparison
+        // includes or excludes the whole period
+        let (lower, upper) = match operator {
+            ">=" => (Bound::Included(start), Bound::Unbounded),
+            ">" if end > start => (Bound::Included(end), Bound::Unbounded),
+            ">" => (Bound::Excluded(start), Bound::Unbounded),
+            "<=" if end > start => (Bound::Unbounded, Bound::Excluded(end)),
+            "<=" => (Bound::Unbounded, Bound::Included(start)),
+            "<" => (Bound::Unbounded, Bound::Excluded(start)),
+            _ if end > start => (Bound::Included(start), Bound::Excluded(end)),
+            _ => (Bound::Included(start), Bound::Included(start)),
+        };
+        Ok(Self {
+            field: field_key,
+            matcher: Matcher::Range(lower, upper),
+        })
+    }
+
+    // the values this filter matches if it only matches exact values, i.e. a regex like ^news$ or
+    // ^(news|worldnews)$ (matched case insensitively). None for every other filter.
+    pub(crate) fn exact_values(&self) -> Option<Vec<String>> {
+        let Matcher::Regex(regex) = &self.matcher else {
+            return None;
+        };
+        let pattern = regex.as_str().strip_prefix('^')?.strip_suffix('$')?;
+        // alternatives are only exact inside a single group: ^news|politics$ means starting with
+        // news or ending with politics
+        let alternatives = match pattern.strip_prefix('(') {
+            Some(group) => group.strip_suffix(')')?,
+            None if pattern.contains('|') => return None,
+            None => pattern,
+        };
+        let values: Vec<String> = alternatives.split('|').map(str::to_lowercase).collect();
+        // anything but plain names could be a regex, e.g. ^news.*$
+        let is_literal = |value: &String| {
+            !value.is_empty()
+                && value
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        };
+        if values.iter().all(is_literal) {
+            Some(values)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn matches(&self, line: &str) -> bool {
+        let value = match field_text(line, &self.field) {
+            Some(value) => value,
+            None => return false,
+        };
+        match &self.matcher {
+            Matcher::Regex(regex) => regex.is_match(&value),
+            Matcher::Range(lower, upper) => match numeric_value(&value) {
+                Some(number) => (*lower, *upper).contains(&number),
+                None => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for FieldFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.matcher {
+            Matcher::Regex(regex) => write!(f, "{}:{}", self.field, regex.as_str()),
+            Matcher::Range(lower, upper) => match (lower, upper) {
+                (Bound::Included(min), Bound::Included(max)) if min == max => {
+                    write!(f, "{}={}", self.field, min)
+                }
+                (Bound::Included(min), Bound::Included(max)) => {
+                    write!(f, "{}:{}..{}", self.field, min, max)
+                }
+                (lower, Bound::Unbounded) => write!(f, "{}{}", self.field, lower_bound(lower)),
+                (Bound::Unbounded, upper) => write!(f, "{}{}", self.field, upper_bound(upper)),
+                (lower, upper) => write!(
+                    f,
+                    "({}{} AND {}{})",
+                    self.field,
+                    lower_bound(lower),
+                    self.field,
+                    upper_bound(upper)
+                ),
+            },
+        }
+    }
+}
+
+fn lower_bound(bound: &Bound<f64>) -> String {
+    match bound {
+        Bound::Included(n) => format!(">={}", n),
+        Bound::Excluded(n) => format!(">{}", n),
+        Bound::Unbounded => String::new(),
+    }
+}
+
+fn upper_bound(bound: &Bound<f64>) -> String {
+    match bound {
+        Bound::Included(n) => format!("<={}", n),
+        Bound::Excluded(n) => format!("<{}", n),
+        Bound::Unbounded => String::new(),
+    }
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+// numbers are used as they are, dates are turned into unix timestamps so they can be compared
+// with created_utc. Returns the start and end of the period the value covers.
+fn parse_endpoint(value: &str) -> Option<(f64, f64)> {
+    if let Some(number) = parse_number(value) {
+        return Some((number, number));
+    }
+    parse_date(value.trim()).map(|(start, end)| (start as f64, end as f64))
+}
+
+// an empty end of a range is unbounded, both ends are inclusive. A date as the upper end includes
+// the whole day or month.
+fn parse_lower(value: &str) -> Option<Bound<f64>> {
+    if value.is_empty() {
+        return Some(Bound::Unbounded);
+    }
+    parse_endpoint(value).map(|(start, _)| Bound::Included(start))
+}
+
+fn parse_upper(value: &str) -> Option<Bound<f64>> {
+    if value.is_empty() {
+        return Some(Bound::Unbounded);
+    }
+    parse_endpoint(value).map(|(start, end)| {
+        if end > start {
+            Bound::Excluded(end)
+        } else {
+            Bound::Included(start)
+        }
+    })
+}
+
+// numbers are sometimes stored as strings in the older dumps, and booleans as true/false instead
+// of 1/0, so both are accepted here
+fn numeric_value(value: &str) -> Option<f64> {
+    match value {
+        "true" => Some(1.0),
+        "false" => Some(0.0),
+        _ => parse_number(value),
+    }
+}
+
+pub(crate) fn process_line(line: &str, query: &Query) -> Option<String> {
     // switched this away from serde_json because it was very slow, and we don't need to parse the whole line
-    if search_strings
-        .iter()
-        .any(|search_string| line.to_lowercase().contains(search_string))
-    {
+    if query.matches(line) {
         Some(line.to_string())
     } else {
         None
     }
 }
 
-pub(crate) fn process_chunk(lines: Vec<String>, search_strings: &Vec<String>) -> Vec<String> {
+pub(crate) fn process_chunk(lines: Vec<String>, query: &Query) -> Vec<String> {
     lines
         .into_par_iter()
-        .filter_map(|line| process_line(&line, search_strings))
+        .filter_map(|line| process_line(&line, query))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exact_values(filter: &str) -> Option<Vec<String>> {
+        FieldFilter::parse(filter).unwrap().exact_values()
+    }
+
+    #[test]
+    fn exact_values_of_literals_and_groups() {
+        assert_eq!(
+            exact_values("subreddit:^News$"),
+            Some(vec!["news".to_string()])
+        );
+        assert_eq!(
+            exact_values("author:^(alice|bob_2)$"),
+            Some(vec!["alice".to_string(), "bob_2".to_string()])
+        );
+    }
+
+    #[test]
+    fn no_exact_values_for_other_regexes() {
+        assert_eq!(exact_values("subreddit:^news|politics$"), None);
+        assert_eq!(exact_values("subreddit:^(news)|(politics)$"), None);
+        assert_eq!(exact_values("subreddit:^news.*$"), None);
+        assert_eq!(exact_values("subreddit:news"), None);
+        assert_eq!(exact_values("subreddit:^()$"), None);
+        assert_eq!(exact_values("score:1..5"), None);
+    }
+
+    #[test]
+    fn a_range_without_ends_is_a_regex() {
+        let filter = FieldFilter::parse("body:..").unwrap();
+        assert!(matches!(filter.matcher, Matcher::Regex(_)));
+        assert!(filter.matches(r#"{"body":"ok"}"#));
+        assert!(!filter.matches(r#"{"body":"a"}"#));
+    }
+
+    fn matches(filter: &str, line: &str) -> bool {
+        FieldFilter::parse(filter).unwrap().matches(line)
+    }
+
+    #[test]
+    fn compares_numbers() {
+        let line = r#"{"score":10,"ups":-2.5,"author":"bob"}"#;
+        assert!(matches("score>=10", line));
+        assert!(!matches("score>10", line));
+        assert!(matches("score<=10", line));
+        assert!(!matches("score<10", line));
+        assert!(matches("score=10", line));
+        assert!(!matches("score=9", line));
+        assert!(matches("ups<-2", line));
+        // values that aren't numbers and missing fields never match
+        assert!(!matches("author>=0", line));
+        assert!(!matches("gilded>=0", line));
+    }
+
+    #[test]
+    fn compares_numbers_stored_as_strings_and_booleans() {
+        let line = r#"{"score":"10","over_18":true,"is_self":false}"#;
+        assert!(matches("score>5", line));
+        assert!(matches("over_18=1", line));
+        assert!(matches("is_self=0", line));
+        assert!(!matches("is_self=1", line));
+    }
+
+    #[test]
+    fn open_and_closed_ranges() {
+        let line = r#"{"score":10}"#;
+        assert!(matches("score:10..", line));
+        assert!(!matches("score:11..", line));
+        assert!(matches("score:..10", line));
+        assert!(!matches("score:..9", line));
+        assert!(matches("score:-5..10", line));
+        assert!(!matches("score:-5..9.5", line));
+        // ends that are neither numbers nor dates make the value a regex
+        assert!(matches("score:a..b", r#"{"score":"a..b"}"#));
+        assert!(FieldFilter::parse("score>abc").is_err());
+    }
+
+    // 2016-09-01T00:00:00Z and the start of the next day
+    const SEP_1: i64 = 1472688000;
+    const SEP_2: i64 = 1472774400;
+
+    fn created(timestamp: i64) -> String {
+        format!(r#"{{"created_utc":{}}}"#, timestamp)
+    }
+
+    #[test]
+    fn compares_whole_days() {
+        let (first, last) = (created(SEP_1), created(SEP_2 - 1));
+        // = covers the whole day
+        assert!(matches("created_utc=2016-09-01", &first));
+        assert!(matches("created_utc=2016-09-01", &last));
+        assert!(!matches("created_utc=2016-09-01", &created(SEP_2)));
+        assert!(!matches("created_utc=2016-09-01", &created(SEP_1 - 1)));
+        // > starts after the day, <= includes all of it
+        assert!(!matches("created_utc>2016-09-01", &last));
+        assert!(matches("created_utc>2016-09-01", &created(SEP_2)));
+        assert!(matches("created_utc<=2016-09-01", &last));
+        assert!(!matches("created_utc<=2016-09-01", &created(SEP_2)));
+        // >= and < start at the beginning of the day
+        assert!(matches("created_utc>=2016-09-01", &first));
+        assert!(!matches("created_utc>=2016-09-01", &created(SEP_1 - 1)));
+        assert!(!matches("created_utc<2016-09-01", &first));
+        assert!(matches("created_utc<2016-09-01", &created(SEP_1 - 1)));
+    }
+
+    #[test]
+    fn compares_whole_months() {
+        // 2016-10-01T00:00:00Z
+        let oct_1 = 1475280000;
+        assert!(matches("created_utc=2016-09", &created(SEP_1)));
+        assert!(matches("created_utc=2016-09", &created(oct_1 - 1)));
+        assert!(!matches("created_utc=2016-09", &created(oct_1)));
+        assert!(!matches("created_utc>2016-09", &created(oct_1 - 1)));
+        assert!(matches("created_utc>2016-09", &created(oct_1)));
+        assert!(matches("created_utc<=2016-09", &created(oct_1 - 1)));
+        assert!(!matches("created_utc<=2016-09", &created(oct_1)));
+    }
+
+    #[test]
+    fn date_ranges_include_their_last_period() {
+        // 2016-11-01T00:00:00Z
+        let nov_1 = 1477958400;
+        assert!(matches("created_utc:2016-09..2016-10", &created(SEP_1)));
+        assert!(matches("created_utc:2016-09..2016-10", &created(nov_1 - 1)));
+        assert!(!matches("created_utc:2016-09..2016-10", &created(nov_1)));
+        assert!(!matches(
+            "created_utc:2016-09..2016-10",
+            &created(SEP_1 - 1)
+        ));
+        assert!(matches("created_utc:2016-09-01..", &created(nov_1)));
+        assert!(!matches("created_utc:..2016-09-01", &created(SEP_2)));
+        assert!(matches("created_utc:..2016-09-01", &created(SEP_2 - 1)));
+        // a point in time is compared exactly
+        assert!(matches(
+            "created_utc:..2016-09-01T12:00:00Z",
+            &created(SEP_1 + 12 * 3600)
+        ));
+        assert!(!matches(
+            "created_utc:..2016-09-01T12:00:00Z",
+            &created(SEP_1 + 12 * 3600 + 1)
+        ));
+    }
+
+    #[test]
+    fn compares_created_utc_stored_as_a_string() {
+        let line = format!(r#"{{"created_utc":"{}"}}"#, SEP_1);
+        assert!(matches("created_utc=2016-09-01", &line));
+        assert!(matches("created_utc<2016-09-02", &line));
+        assert!(!matches("created_utc>2016-09-01", &line));
+    }
+}
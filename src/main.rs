@@ -1,40 +1,170 @@
+// status messages (warnings, verbose output and the summaries) go to stdout, unless the matches
+// themselves are written to stdout with `-o -`, then they are moved to stderr
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::STATUS_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod aggregation;
 mod arguments;
 mod constants;
+mod dates;
+mod frames;
+mod index;
+mod inputs;
+mod json;
+mod line_counts;
 mod line_processing;
+mod output;
+mod parquet_output;
+mod query;
+mod sampling;
+mod seekable;
+mod sqlite_output;
+mod timeseries;
 
 extern crate num_cpus;
 
-use crate::arguments::CommandLineArgs;
-use crate::line_processing::process_chunk;
-use constants::create_line_count_map;
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::aggregation::{Counts, COUNT_COLUMN};
+use crate::arguments::{CommandLineArgs, Subcommand};
+use crate::index::{index_path, BlockIndex, PlannedBlock};
+use crate::inputs::{
+    file_name, open_counted_input, open_input, resolve_dump_range, resolve_inputs, DumpKind, STDIN,
+};
+use crate::line_counts::LineCounts;
+use crate::line_processing::{process_chunk, FieldFilter};
+use crate::output::{
+    typed_columns, Column, CompressedWriter, Compression, CsvWriter, JsonWriter, OutputFormat,
+    RecordWriter,
+};
+use crate::parquet_output::{ParquetWriter, PartitionedParquetWriter};
+use crate::query::Query;
+use crate::sampling::{Sampling, SamplingWriter};
+use crate::sqlite_output::SqliteWriter;
+use crate::timeseries::TimeseriesWriter;
+use indicatif::{HumanCount, MultiProgress, ProgressBar, ProgressStyle};
 use rayon::ThreadPoolBuilder;
-use std::fs::File;
+use std::fs;
 use std::fs::OpenOptions;
 use std::io;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::string::String;
-use zstd::Decoder;
-
-// this is mostly a utility function to get the number of lines in a file, used for creating the
-// estimates used in the progress bar. I've left it in because it might be useful for something
-// else in the future. Due to the bottleneck being the disk read speed, it'll take about the
-// same time as using the program normally.
-fn count_lines(file_name: &str) -> () {
-    let input_buf = PathBuf::from(file_name);
-    let metadata = input_buf.metadata().unwrap();
-    let input_file = File::open(input_buf).unwrap();
-    let mut decoder = Decoder::new(input_file).unwrap();
-    decoder.window_log_max(31).unwrap();
-    let input_stream = BufReader::new(decoder);
-    let num_lines = input_stream.lines().count();
-
-    println!("{};{};{}", file_name, metadata.len(), num_lines);
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+// the number of chunks the reader thread can read ahead of the search
+const CHANNEL_CAPACITY: usize = 4;
+
+// counts the lines of a file and prints them as `file;size;lines`. The count is also cached for
+// the progress bar of later searches, see line_counts.rs.
+fn count_lines(input_buf: &Path) -> io::Result<u64> {
+    let file_name = input_buf.display();
+    let size = input_buf
+        .metadata()
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    let input_stream = open_input(input_buf)?;
+    let num_lines = input_stream.lines().count() as u64;
+
+    println!("{};{};{}", file_name, size, num_lines);
+    Ok(num_lines)
+}
+
+// writes a block index for each of the input files, see index.rs
+fn index_files(inputs: &[String], block_lines: u64) -> io::Result<()> {
+    let input_files = match resolve_inputs(inputs) {
+        Ok(input_files) => input_files,
+        Err(err_msg) => {
+            eprintln!("{}", err_msg);
+            return Ok(());
+        }
+    };
+    for input_file in &input_files {
+        if input_file.as_os_str() == STDIN {
+            eprintln!("stdin can't be indexed.");
+            continue;
+        }
+        let start = Instant::now();
+        let index = BlockIndex::build(input_file, block_lines)?;
+        let path = index_path(input_file);
+        index.write(&path)?;
+        println!(
+            "Indexed {} lines of {} in {} blocks, written to {} (took {})",
+            index.lines(),
+            input_file.display(),
+            index.block_count(),
+            path.display(),
+            format_duration(start.elapsed().as_secs())
+        );
+    }
+    Ok(())
+}
+
+fn convert_files(
+    inputs: &[String],
+    output_dir: &str,
+    frame_size: usize,
+    level: i32,
+    overwrite: bool,
+) -> io::Result<()> {
+    let input_files = match resolve_inputs(inputs) {
+        Ok(input_files) => input_files,
+        Err(err_msg) => {
+            eprintln!("{}", err_msg);
+            return Ok(());
+        }
+    };
+    fs::create_dir_all(output_dir)?;
+    for input_file in &input_files {
+        if input_file.as_os_str() == STDIN {
+            eprintln!("stdin can't be converted.");
+            continue;
+        }
+        let output_file = Path::new(output_dir).join(input_file.file_name().unwrap_or_default());
+        if output_file.exists() && fs::canonicalize(&output_file)? == fs::canonicalize(input_file)?
+        {
+            eprintln!(
+                "{} would overwrite itself, choose a different output directory.",
+                input_file.display()
+            );
+            continue;
+        }
+        if output_file.exists() && !overwrite {
+            eprintln!(
+                "{} already exists, use --overwrite to replace it.",
+                output_file.display()
+            );
+            continue;
+        }
+        let start = Instant::now();
+        let mut input_stream = open_input(input_file)?;
+        let frames = seekable::convert(&mut input_stream, &output_file, frame_size, level)?;
+        println!(
+            "Converted {} to {} with {} frames (took {})",
+            input_file.display(),
+            output_file.display(),
+            frames,
+            format_duration(start.elapsed().as_secs())
+        );
+    }
+    Ok(())
 }
 
 fn main() -> std::io::Result<()> {
     let mut args = CommandLineArgs::new().unwrap();
+    if args.output == "-" {
+        STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+    }
 
     // set the number of threads to use
     ThreadPoolBuilder::new()
@@ -42,67 +172,432 @@ fn main() -> std::io::Result<()> {
         .build_global()
         .unwrap();
 
+    if let Some(Subcommand::Index { input, block_lines }) = args.subcommand.as_ref() {
+        return index_files(input, *block_lines);
+    }
+    if let Some(Subcommand::Convert {
+        input,
+        output_dir,
+        frame_size,
+        level,
+        overwrite,
+    }) = args.subcommand.as_ref()
+    {
+        return convert_files(input, output_dir, *frame_size, *level, *overwrite);
+    }
+
+    let mut input_files = match resolve_inputs(&args.input) {
+        Ok(input_files) => input_files,
+        Err(err_msg) => {
+            eprintln!("{}", err_msg);
+            return Ok(());
+        }
+    };
+    if let Some(dump_dir) = args.dump_dir.as_ref() {
+        match resolve_dump_range(
+            dump_dir,
+            args.from.as_deref(),
+            args.to.as_deref(),
+            args.kind,
+        ) {
+            Ok(dump_files) => input_files.extend(dump_files),
+            Err(err_msg) => {
+                eprintln!("{}", err_msg);
+                return Ok(());
+            }
+        }
+    }
+
+    let mut line_counts = LineCounts::load();
     if args.linecount {
-        count_lines(&args.input);
+        for input_file in &input_files {
+            let lines = count_lines(input_file)?;
+            if input_file.as_os_str() != STDIN {
+                line_counts.insert(input_file, lines);
+            }
+        }
+        line_counts.save();
         return Ok(());
     }
 
-    let search_fields: Vec<String>;
-    if args.preset.is_some() {
-        search_fields = arguments::get_preset_fields(&args.preset.unwrap()).unwrap();
-    } else {
-        // if the preset is not set, we can assume that the fields are set
-        search_fields = args
+    let search_fields: Vec<String> = match args.preset.as_ref() {
+        Some(preset) => arguments::get_preset_fields(preset).unwrap(),
+        // if the preset is not set, the fields or a query are set
+        None => args
             .fields
             .as_ref()
             .unwrap()
             .iter()
             .map(|s| s.to_string())
-            .collect();
-    }
+            .collect(),
+    };
 
-    let mut search_strings: Vec<String> = Vec::new();
+    let mut filters: Vec<FieldFilter> = Vec::new();
     for field in search_fields {
-        let mut split = field.split(':');
-        // test if split contains two elements
-        if split.clone().count() != 2 {
-            let err_msg = format!("Field {} is not in the format <field>:<value>", field);
-            eprintln!("{}", err_msg);
-            return Ok(());
+        match FieldFilter::parse(&field) {
+            Ok(filter) => filters.push(filter),
+            Err(err_msg) => {
+                eprintln!("{}", err_msg);
+                return Ok(());
+            }
+        }
+    }
+    let mut queries: Vec<Query> = Vec::new();
+    if !filters.is_empty() {
+        queries.push(Query::any(filters));
+    }
+    // a query given together with fields or a preset narrows down their results
+    if let Some(expression) = args.query.as_ref() {
+        match Query::parse(expression) {
+            Ok(query) => queries.push(query),
+            Err(err_msg) => {
+                eprintln!("{}", err_msg);
+                return Ok(());
+            }
+        }
+    }
+    // --after and --before are shorthands for comparisons on created_utc
+    if let Some(after) = args.after.as_ref() {
+        match FieldFilter::parse(&format!("created_utc>={}", after)) {
+            Ok(filter) => queries.push(Query::Field(filter)),
+            Err(err_msg) => {
+                eprintln!("{}", err_msg);
+                return Ok(());
+            }
+        }
+    }
+    if let Some(before) = args.before.as_ref() {
+        match FieldFilter::parse(&format!("created_utc<{}", before)) {
+            Ok(filter) => queries.push(Query::Field(filter)),
+            Err(err_msg) => {
+                eprintln!("{}", err_msg);
+                return Ok(());
+            }
+        }
+    }
+    let query = if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        Query::And(queries)
+    };
+    // warn about fields that can never match because the dump kind does not have them, e.g.
+    // searching comments for a title
+    let mut warned_fields: Vec<(&str, DumpKind)> = Vec::new();
+    for input_file in &input_files {
+        let Some(kind) = DumpKind::from_file_name(&file_name(input_file)) else {
+            continue;
+        };
+        for field in query.fields() {
+            let top_level = field.split('.').next().unwrap_or(field);
+            if kind.foreign_fields().contains(&top_level) && !warned_fields.contains(&(field, kind))
+            {
+                status!(
+                    "Warning: {} is not a field of {} dumps and will never match in {}* files.",
+                    field,
+                    kind.name(),
+                    kind.prefix()
+                );
+                warned_fields.push((field, kind));
+            }
         }
-        let field_key = split.next().unwrap().to_lowercase();
-        let value = split.next().unwrap().to_lowercase();
-        // if the value is an integer, a boolean or null do not add quotes
-        if value.parse::<i64>().is_ok() || value == "true" || value == "false" || value == "null" {
-            search_strings.push(format!("\"{}\": {}", field_key, value));
-            search_strings.push(format!("\"{}\":{}", field_key, value));
+    }
+
+    let mut output_stream = match create_output(&mut args, &input_files)? {
+        Some(output_stream) => output_stream,
+        None => return Ok(()),
+    };
+    // with --count or --group-by the matches are only counted, the counts are written at the end
+    let mut counts = if args.count || args.group_by.is_some() {
+        Some(Counts::new(args.group_by.as_deref().unwrap_or(&[])))
+    } else {
+        None
+    };
+    // sampling draws from the matches, so it sits between the search and the output
+    let sampling = match (args.sample_rate, args.sample_size, args.stratify_by) {
+        (Some(rate), _, _) => Some(Sampling::Rate(rate)),
+        (_, Some(size), Some(stratum)) => Some(Sampling::Stratified(stratum, size)),
+        (_, Some(size), None) => Some(Sampling::Size(size)),
+        _ => None,
+    };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    if let Some(sampling) = sampling {
+        output_stream = Box::new(SamplingWriter::new(output_stream, sampling, seed));
+    }
+    // the time series counts all matches, including those left out of a sample
+    if let Some(bucket) = args.timeseries {
+        output_stream = Box::new(TimeseriesWriter::new(
+            output_stream,
+            bucket,
+            args.split_by.clone(),
+            PathBuf::from(&args.timeseries_output),
+        ));
+    }
+
+    // if the debug flag is set, print some general info
+    if args.verbose {
+        status!(
+            "Starting reddit-search for {} file(s) ({} threads) at {}",
+            input_files.len(),
+            rayon::current_num_threads(),
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+        );
+        for input_file in &input_files {
+            status!("Input file: {}", input_file.display());
+        }
+        status!("Output file: {}", args.output);
+        status!("Append: {}", args.append);
+        status!("Format: {:?}", args.format);
+        status!("Threads: {}", rayon::current_num_threads());
+        status!("Query: {}", query);
+        status!("Chunk size: {}", args.chunk_size);
+        if let Some(select) = args.select.as_ref() {
+            status!("Selected fields: {}", select.join(", "));
+        }
+        if let Some(sampling) = sampling {
+            status!("Sampling: {} (seed {})", sampling, seed);
+        }
+    }
+
+    // look up the line counts and sizes of all files up front. Files with a known line count show
+    // their progress in lines, all others and the overall progress bar in compressed bytes. The
+    // length of stdin isn't known, its lines are counted while searching.
+    let mut num_lines: Vec<u64> = Vec::with_capacity(input_files.len());
+    let mut file_sizes: Vec<u64> = Vec::with_capacity(input_files.len());
+    for input_file in &input_files {
+        num_lines.push(line_counts.get(input_file).unwrap_or(0));
+        if input_file.as_os_str() == STDIN {
+            file_sizes.push(0);
         } else {
-            // otherwise, add quotes
-            search_strings.push(format!("\"{}\": \"{}\"", field_key, value));
-            search_strings.push(format!("\"{}\":\"{}\"", field_key, value));
+            file_sizes.push(input_file.metadata()?.len());
         }
     }
-    // check if the input file exists
-    let input_buf = PathBuf::from(args.input.clone());
-    if !input_buf.exists() {
-        let err_msg = format!("Input file {} does not exist.", args.input);
-        eprintln!("{}", err_msg);
-        return Ok(());
+
+    let progress = MultiProgress::new();
+    // with a single file the overall progress would be the same as the file progress
+    let overall_pb = if input_files.len() > 1 {
+        let pb = progress.add(ProgressBar::new(file_sizes.iter().sum()));
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "[{elapsed_precise}] [{bar:40.green/white}] file {msg} | {percent}% | {eta} left",
+                )
+                .expect("Failed to set progress bar style")
+                .progress_chars("=> "),
+        );
+        Some(pb)
+    } else {
+        None
+    };
+
+    let mut total_matched_lines = 0;
+    let mut total_searched_lines = 0;
+    // files after the one reaching the limit are never searched
+    let mut searched_files = 0;
+    let mut searched_bytes = 0;
+    let start = Instant::now();
+    for (i, input_file) in input_files.iter().enumerate() {
+        if let Some(overall_pb) = &overall_pb {
+            overall_pb.set_message(format!("{}/{}", i + 1, input_files.len()));
+        }
+        let in_bytes = num_lines[i] == 0 && input_file.as_os_str() != STDIN;
+        let (length, template) = if in_bytes {
+            (file_sizes[i], "[{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({msg}) | {percent}% | {eta} left")
+        } else if num_lines[i] == 0 {
+            (0, "[{elapsed_precise}] {spinner} {human_pos} lines")
+        } else {
+            (num_lines[i], "[{elapsed_precise}] [{bar:40.cyan/blue}] {human_pos}/{human_len} | {percent}% | {eta} left")
+        };
+        let pb = progress.add(ProgressBar::new(length));
+        if in_bytes {
+            pb.set_message("0 lines");
+        }
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(template)
+                .expect("Failed to set progress bar style")
+                .progress_chars("=> "),
+        );
+
+        let FileSearch {
+            matched_lines: matched_lines_count,
+            searched_lines,
+            complete,
+        } = match search_file(
+            input_file,
+            &query,
+            args.chunk_size,
+            args.limit.map(|limit| limit - total_matched_lines),
+            match counts.as_mut() {
+                Some(counts) => Sink::Count(counts),
+                None => Sink::Write(output_stream.as_mut()),
+            },
+            Progress {
+                file: &pb,
+                in_bytes,
+                overall: overall_pb.as_ref(),
+            },
+        ) {
+            Ok(result) => result,
+            // the reading end of a pipe was closed, e.g. by `| head`, nothing more can be written
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                progress.clear()?;
+                line_counts.save();
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        total_matched_lines += matched_lines_count;
+        total_searched_lines += searched_lines;
+        searched_files += 1;
+        searched_bytes += file_sizes[i];
+        if let Some(overall_pb) = &overall_pb {
+            overall_pb.set_position(searched_bytes);
+        }
+        // only a search that read the whole file knows its line count
+        if complete {
+            num_lines[i] = searched_lines;
+            if input_file.as_os_str() != STDIN {
+                line_counts.insert(input_file, searched_lines);
+            }
+        }
+
+        pb.finish_and_clear();
+        progress.remove(&pb);
+        if args.limit == Some(total_matched_lines) {
+            progress.suspend(|| {
+                status!(
+                    "Reached the limit of {} matches after {} lines in file {} (took {})",
+                    total_matched_lines,
+                    searched_lines,
+                    input_file.display(),
+                    format_duration(pb.elapsed().as_secs())
+                )
+            });
+            break;
+        }
+        progress.suspend(|| {
+            status!(
+                "Matched {} lines out of {} in file {} (took {})",
+                matched_lines_count,
+                num_lines[i],
+                input_file.display(),
+                format_duration(pb.elapsed().as_secs())
+            )
+        });
+    }
+    line_counts.save();
+    if let Some(counts) = counts.as_mut() {
+        let groups = counts.write_to(output_stream.as_mut())?;
+        progress.suspend(|| status!("Wrote counts for {} group(s)", groups));
+    }
+    output_stream.finish()?;
+
+    if let Some(overall_pb) = overall_pb {
+        overall_pb.finish_and_clear();
+        status!(
+            "Matched {} lines out of {} in {} file(s) (took {})",
+            total_matched_lines,
+            total_searched_lines,
+            searched_files,
+            format_duration(start.elapsed().as_secs())
+        );
     }
-    let metadata = input_buf.metadata()?;
-    // check if input file exists and is a file
-    if !metadata.is_file() {
-        let err_msg = format!("Input file {} does not exist.", args.input);
+
+    Ok(())
+}
+
+// opens the output and creates the writer for the chosen format. Returns None if the output can't
+// be used or the user chose not to touch an existing output file.
+fn create_output(
+    args: &mut CommandLineArgs,
+    input_files: &[PathBuf],
+) -> io::Result<Option<Box<dyn RecordWriter>>> {
+    if args.partition_by.is_some() && args.format != OutputFormat::Parquet {
+        eprintln!("--partition-by is only supported for parquet output.");
+        return Ok(None);
+    }
+    if args.fts && args.format != OutputFormat::Sqlite {
+        eprintln!("--fts is only supported for sqlite output.");
+        return Ok(None);
+    }
+    let compression = args
+        .compress
+        .unwrap_or_else(|| Compression::from_extension(&args.output));
+    if compression != Compression::None
+        && matches!(args.format, OutputFormat::Parquet | OutputFormat::Sqlite)
+    {
+        eprintln!("Compression is only supported for json, csv and tsv output.");
+        return Ok(None);
+    }
+    if let Some(Err(err_msg)) = args
+        .compression_level
+        .map(|level| compression.check_level(level))
+    {
         eprintln!("{}", err_msg);
-        return Ok(());
+        return Ok(None);
     }
 
-    let input_file = File::open(input_buf)?;
-    let mut decoder = Decoder::new(input_file)?;
-    decoder.window_log_max(31)?;
-    let input_stream = BufReader::new(decoder);
+    // the columns of table formats, json output uses --select directly
+    let (kind, columns) = output_columns(args, input_files);
+    // counts are integers unless declared otherwise, the first declaration of a column is used
+    let mut schema = args.schema.clone();
+    if args.count || args.group_by.is_some() {
+        schema.push(format!("{}:int", COUNT_COLUMN));
+    }
+    let columns = match typed_columns(&columns, &schema) {
+        Ok(columns) => columns,
+        Err(err_msg) => {
+            eprintln!("{}", err_msg);
+            return Ok(None);
+        }
+    };
+    // `-o -` streams the matches to stdout, e.g. to pipe them into jq or another reddit-search
+    if args.output == "-" {
+        if matches!(args.format, OutputFormat::Parquet | OutputFormat::Sqlite) {
+            eprintln!("Parquet and sqlite output can't be written to stdout.");
+            return Ok(None);
+        }
+        let output = CompressedWriter::new(
+            Box::new(io::stdout()),
+            compression,
+            args.compression_level,
+            args.compression_threads,
+        )?;
+        return Ok(Some(text_writer(args, output, columns, true)?));
+    }
+    // partitioned output is a directory of parquet files, which is only written if it is new
+    if let Some(partitioning) = args.partition_by {
+        let directory = PathBuf::from(args.output.clone());
+        let is_empty = directory
+            .read_dir()
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(!directory.exists());
+        if !is_empty {
+            eprintln!(
+                "Output directory {} already exists and is not empty.",
+                args.output
+            );
+            return Ok(None);
+        }
+        return Ok(Some(Box::new(PartitionedParquetWriter::new(
+            directory,
+            partitioning,
+            columns,
+        )?)));
+    }
 
     if PathBuf::from(args.output.clone()).exists() && !args.append && !args.overwrite {
+        // the answer would be read from the input data
+        if input_files
+            .iter()
+            .any(|input_file| input_file.as_os_str() == STDIN)
+        {
+            eprintln!(
+                "File {} already exists. Use --append or --overwrite when reading from stdin.",
+                args.output
+            );
+            return Ok(None);
+        }
         eprint!("File {} already exists. Enter 'a' to append to the file, 'o' to overwrite, or anything else to exit: ", args.output.clone());
         let mut user_input = String::new();
         io::stdin()
@@ -114,10 +609,14 @@ fn main() -> std::io::Result<()> {
         } else if user_input == "o" {
             args.append = false;
         } else {
-            println!("Exiting");
-            return Ok(());
+            status!("Exiting");
+            return Ok(None);
         }
     }
+    if args.append && args.format == OutputFormat::Parquet {
+        eprintln!("Appending is not supported for parquet output.");
+        return Ok(None);
+    }
     // if append is false (i.e. overwrite) and the file exists, empty it
     if !args.append && PathBuf::from(args.output.clone()).exists() {
         let mut output_file = OpenOptions::new()
@@ -129,100 +628,264 @@ fn main() -> std::io::Result<()> {
     let output_buf = PathBuf::from(args.output.clone());
     let output_file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(output_buf)?;
 
-    // if the debug flag is set, print some general info
-    if args.verbose {
-        println!(
-            "Starting reddit-search for {} ({} threads) at {}",
-            args.input,
-            rayon::current_num_threads(),
-            chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
-        );
-        println!("Input file: {}", args.input);
-        println!("Output file: {}", args.output);
-        println!("Append: {}", args.append);
-        println!("Threads: {}", rayon::current_num_threads());
-        println!("Line count: {}", metadata.len());
-        println!("Search fields: {}", search_strings.join(", "));
-        println!("Chunk size: {}", args.chunk_size);
+    let output_stream: Box<dyn RecordWriter> = match args.format {
+        OutputFormat::Json | OutputFormat::Csv | OutputFormat::Tsv => {
+            // only write the header if we are not appending to an existing table
+            let write_header = output_file.metadata()?.len() == 0;
+            let output = CompressedWriter::new(
+                Box::new(output_file),
+                compression,
+                args.compression_level,
+                args.compression_threads,
+            )?;
+            text_writer(args, output, columns, write_header)?
+        }
+        OutputFormat::Parquet => Box::new(ParquetWriter::new(output_file, columns)?),
+        OutputFormat::Sqlite => {
+            // the database is opened by SQLite itself, the (possibly emptied) file is not needed
+            drop(output_file);
+            Box::new(SqliteWriter::new(
+                &args.output,
+                kind.name(),
+                columns,
+                args.fts,
+            )?)
+        }
+    };
+    Ok(Some(output_stream))
+}
+
+// the writer for the text formats json, csv and tsv
+fn text_writer(
+    args: &CommandLineArgs,
+    output: CompressedWriter,
+    columns: Vec<Column>,
+    write_header: bool,
+) -> io::Result<Box<dyn RecordWriter>> {
+    Ok(match args.format {
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let delimiter = if args.format == OutputFormat::Tsv {
+                b'\t'
+            } else {
+                b','
+            };
+            Box::new(CsvWriter::new(
+                output,
+                delimiter,
+                columns.into_iter().map(|column| column.name).collect(),
+                write_header,
+            )?)
+        }
+        _ => Box::new(JsonWriter::new(output, args.select.clone())),
+    })
+}
+
+// the columns for table outputs: the selected fields, or a default set depending on whether
+// comments or submissions are searched. Also returns which of the two is searched.
+fn output_columns(args: &CommandLineArgs, input_files: &[PathBuf]) -> (DumpKind, Vec<String>) {
+    let kinds: Vec<DumpKind> = input_files
+        .iter()
+        .filter_map(|input_file| DumpKind::from_file_name(&file_name(input_file)))
+        .collect();
+    let kind = kinds.first().copied().unwrap_or(DumpKind::Comments);
+    if args.count || args.group_by.is_some() {
+        let mut columns = args.group_by.clone().unwrap_or_default();
+        columns.push(COUNT_COLUMN.to_string());
+        return (kind, columns);
+    }
+    if let Some(select) = args.select.as_ref() {
+        return (kind, select.clone());
+    }
+    if args.format != OutputFormat::Json && kinds.iter().any(|other| *other != kind) {
+        status!("Warning: Searching both comments and submissions, using the {} columns. Use --select to choose the columns.", kind.name());
     }
+    let columns = kind
+        .default_columns()
+        .iter()
+        .map(|column| column.to_string())
+        .collect();
+    (kind, columns)
+}
+
+// lines sent from the reader to the search, along with the number of lines the reader skipped
+// since the previous chunk because the block index ruled them out
+struct Chunk {
+    lines: Vec<String>,
+    skipped_lines: u64,
+    // set on the last chunk, once the reader reached the end of the input
+    end_of_input: bool,
+}
+
+impl Chunk {
+    fn new(chunk_size: usize) -> Self {
+        Self {
+            lines: Vec::with_capacity(chunk_size),
+            skipped_lines: 0,
+            end_of_input: false,
+        }
+    }
+}
+
+// the result of searching a single input file
+struct FileSearch {
+    matched_lines: usize,
+    searched_lines: u64,
+    // false if the search stopped at the limit before the end of the input
+    complete: bool,
+}
+
+// where the matches of a search end up
+enum Sink<'a> {
+    Write(&'a mut dyn RecordWriter),
+    Count(&'a mut Counts),
+}
+
+// the progress bars of a search. The file progress bar counts lines, or compressed bytes if
+// `in_bytes` is set, the overall progress bar always counts compressed bytes.
+struct Progress<'a> {
+    file: &'a ProgressBar,
+    in_bytes: bool,
+    overall: Option<&'a ProgressBar>,
+}
+
+// searches a single input file and writes the matches to the output or only counts them. With a
+// limit, reading stops as soon as that many matches have been written.
+fn search_file(
+    input_file: &Path,
+    query: &Query,
+    chunk_size: usize,
+    limit: Option<usize>,
+    mut sink: Sink,
+    progress: Progress,
+) -> io::Result<FileSearch> {
+    let compressed_position = Arc::new(AtomicU64::new(0));
+    let mut input_stream = open_counted_input(input_file, compressed_position.clone())?;
+    // with a block index, blocks that can't contain a match are skipped. Without one the whole
+    // input is a single block, anything after the last indexed block is searched as well.
+    let mut blocks = match BlockIndex::open(input_file)? {
+        Some(index) => index.plan(query),
+        None => Vec::new(),
+    };
+    blocks.push(PlannedBlock {
+        size: u64::MAX,
+        lines: u64::MAX,
+        search: true,
+    });
 
     let mut matched_lines_count = 0;
-    let line_count_map = create_line_count_map();
-    let file_name = args.input.split('/').last().unwrap();
-    let mut num_lines = *line_count_map.get(file_name).unwrap_or(&0);
-    if num_lines == 0 {
-        println!("Warning: No line count found for {}. This will cause the progress percent to be inaccurate.", file_name);
-        // estimate the number of lines as approximately 10,000,000 per GB
-        let estimated_num_lines = (metadata.len() as f64 / 1_000_000_000.0) * 10_000_000.0;
-        num_lines = estimated_num_lines as u64;
-    }
-    let pb = ProgressBar::new(num_lines);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "[{elapsed_precise}] [{bar:40.cyan/blue}] {human_pos}/{human_len} | {percent}% | {eta} left",
-            )
-            .expect("Failed to set progress bar style")
-            .progress_chars("=> "),
-    );
-
-    let mut output_stream = BufWriter::new(output_file);
-    let (tx, rx) = std::sync::mpsc::channel();
-
-    // spawn threads to read the input file and send chunks to the main thread
-    rayon::spawn(move || {
-        let mut chunk = Vec::with_capacity(args.chunk_size);
-        for line in input_stream.lines() {
-            let line = line.expect("Failed to read line");
-            chunk.push(line);
-
-            if chunk.len() >= args.chunk_size {
-                tx.send(chunk).expect("Failed to send chunk");
-                chunk = Vec::with_capacity(args.chunk_size);
+    let mut searched_lines = 0;
+    let mut searched_bytes = 0;
+    let mut complete = false;
+    // the channel is bounded so the reader doesn't decompress far ahead of the search, which
+    // also lets it stop quickly once the limit is reached
+    let (tx, rx) = std::sync::mpsc::sync_channel(CHANNEL_CAPACITY);
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader_stop = stop.clone();
+
+    // read the input file on its own thread and send chunks to the main thread. This is not a
+    // rayon thread, a reader blocked on the full channel would otherwise hold up the search. A read
+    // error is sent in place of a chunk and ends the search.
+    thread::spawn(move || {
+        let mut chunk = Chunk::new(chunk_size);
+        for block in blocks {
+            if !block.search {
+                // skipped blocks of seekable files are never decompressed, other inputs still
+                // have to be decompressed but aren't split into lines
+                if let Err(e) = input_stream.skip(block.size) {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+                chunk.skipped_lines += block.lines;
+                continue;
+            }
+            for line in (&mut input_stream).lines().take(block.lines as usize) {
+                match line {
+                    Ok(line) => chunk.lines.push(line),
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                }
+
+                if chunk.lines.len() >= chunk_size {
+                    // the receiver is gone if writing the output failed, stop reading
+                    if reader_stop.load(Ordering::Relaxed) || tx.send(Ok(chunk)).is_err() {
+                        return;
+                    }
+                    chunk = Chunk::new(chunk_size);
+                }
             }
         }
 
-        if !chunk.is_empty() {
-            tx.send(chunk).expect("Failed to send final chunk");
+        // the last chunk is sent even if it is empty, it tells the search the input is complete
+        chunk.end_of_input = true;
+        if !reader_stop.load(Ordering::Relaxed) {
+            let _ = tx.send(Ok(chunk));
         }
     });
 
     // process the chunks and write the matches to the output file
-    for chunk in rx {
-        let matches = process_chunk(chunk, &search_strings);
-        matched_lines_count += matches.len();
-        for line in matches {
-            writeln!(output_stream, "{}", line)?;
-        }
-        pb.inc(args.chunk_size as u64);
-    }
-
-    pb.finish_and_clear();
-    print!(
-        "Matched {} lines out of {} in file {}",
-        matched_lines_count, num_lines, args.input
-    );
-    if pb.elapsed().as_secs() > 60 {
-        if pb.elapsed().as_secs() > 120 {
-            println!(
-                " (took {} minutes, {} seconds)",
-                pb.elapsed().as_secs() / 60,
-                pb.elapsed().as_secs() % 60
+    for chunk in rx.iter() {
+        let chunk = chunk.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", input_file.display(), e),
             )
+        })?;
+        let chunk_len = chunk.lines.len() as u64 + chunk.skipped_lines;
+        complete = chunk.end_of_input;
+        match &mut sink {
+            Sink::Write(output_stream) => {
+                let mut matches = process_chunk(chunk.lines, query);
+                if let Some(limit) = limit {
+                    matches.truncate(limit - matched_lines_count);
+                }
+                matched_lines_count += matches.len();
+                for line in matches {
+                    output_stream.write_record(&line)?;
+                }
+            }
+            Sink::Count(counts) => matched_lines_count += counts.count_chunk(chunk.lines, query),
+        }
+        searched_lines += chunk_len;
+        // the reader is a few chunks ahead, which the bars don't need to be exact about
+        let position = compressed_position.load(Ordering::Relaxed);
+        if progress.in_bytes {
+            progress.file.set_position(position);
+            progress
+                .file
+                .set_message(format!("{} lines", HumanCount(searched_lines)));
         } else {
-            println!(
-                " (took {} minute, {} seconds)",
-                pb.elapsed().as_secs() / 60,
-                pb.elapsed().as_secs() % 60
-            )
+            progress.file.inc(chunk_len);
+        }
+        if let Some(overall_pb) = progress.overall {
+            overall_pb.inc(position.saturating_sub(searched_bytes));
+        }
+        searched_bytes = searched_bytes.max(position);
+        if limit == Some(matched_lines_count) {
+            stop.store(true, Ordering::Relaxed);
+            break;
         }
-    } else {
-        println!(" (took {} seconds)", pb.elapsed().as_secs());
     }
+    // let the reader finish a send it may be blocked on so it sees the stop flag
+    for _ in rx.iter() {}
 
-    Ok(())
+    Ok(FileSearch {
+        matched_lines: matched_lines_count,
+        searched_lines,
+        complete,
+    })
+}
+
+fn format_duration(seconds: u64) -> String {
+    if seconds > 120 {
+        format!("{} minutes, {} seconds", seconds / 60, seconds % 60)
+    } else if seconds > 60 {
+        format!("{} minute, {} seconds", seconds / 60, seconds % 60)
+    } else {
+        format!("{} seconds", seconds)
+    }
 }
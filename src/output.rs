@@ -0,0 +1,422 @@
+use crate::constants::{BOOLEAN_FIELDS, FLOAT_FIELDS, INTEGER_FIELDS};
+use crate::json::{escape, find_value, value_text};
+use flate2::write::GzEncoder;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum OutputFormat {
+    Json,
+    Csv,
+    Tsv,
+    Parquet,
+    Sqlite,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            _ => Err(format!(
+                "Unknown format {}, must be one of json, csv, tsv, parquet, sqlite",
+                format
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Compression {
+    None,
+    Zstd,
+    Gzip,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(compression: &str) -> Result<Self, Self::Err> {
+        match compression {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err(format!(
+                "Unknown compression {}, must be one of none, zstd, gzip",
+                compression
+            )),
+        }
+    }
+}
+
+impl Compression {
+    pub(crate) fn check_level(&self, level: i32) -> Result<(), String> {
+        let range = match self {
+            Compression::None => return Ok(()),
+            Compression::Zstd => zstd::compression_level_range(),
+            Compression::Gzip => 0..=9,
+        };
+        if range.contains(&level) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Compression level must be between {} and {} for {:?}",
+                range.start(),
+                range.end(),
+                self
+            ))
+        }
+    }
+
+    // picks the compression from the extension of the output file, .zst or .gz
+    pub(crate) fn from_extension(output: &str) -> Self {
+        if output.ends_with(".zst") {
+            Compression::Zstd
+        } else if output.ends_with(".gz") {
+            Compression::Gzip
+        } else {
+            Compression::None
+        }
+    }
+}
+
+// the output of the text formats (a file or stdout), optionally compressed. Appending to a
+// compressed file adds a new zstd frame or gzip member, which decoders (including our own input)
+// read as one stream.
+pub(crate) enum CompressedWriter {
+    Plain(BufWriter<Box<dyn Write>>),
+    Zstd(BufWriter<zstd::Encoder<'static, Box<dyn Write>>>),
+    Gzip(BufWriter<GzEncoder<Box<dyn Write>>>),
+}
+
+impl CompressedWriter {
+    // a level of None uses the default of the compression, threads are only used by zstd
+    pub(crate) fn new(
+        output: Box<dyn Write>,
+        compression: Compression,
+        level: Option<i32>,
+        threads: u32,
+    ) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => CompressedWriter::Plain(BufWriter::new(output)),
+            Compression::Zstd => {
+                let level = level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL);
+                let mut encoder = zstd::Encoder::new(output, level)?;
+                if threads > 1 {
+                    encoder.multithread(threads)?;
+                }
+                CompressedWriter::Zstd(BufWriter::new(encoder))
+            }
+            Compression::Gzip => {
+                let level = level.unwrap_or(6);
+                let encoder = GzEncoder::new(output, flate2::Compression::new(level as u32));
+                CompressedWriter::Gzip(BufWriter::new(encoder))
+            }
+        })
+    }
+
+    // flushes everything and writes the end of the compressed stream
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.flush(),
+            CompressedWriter::Zstd(writer) => {
+                writer.flush()?;
+                writer.get_mut().do_finish()
+            }
+            CompressedWriter::Gzip(writer) => {
+                writer.flush()?;
+                writer.get_mut().try_finish()
+            }
+        }
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.write(buf),
+            CompressedWriter::Zstd(writer) => writer.write(buf),
+            CompressedWriter::Gzip(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(writer) => writer.flush(),
+            CompressedWriter::Zstd(writer) => writer.flush(),
+            CompressedWriter::Gzip(writer) => writer.flush(),
+        }
+    }
+}
+
+// the type of a column in typed outputs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ColumnType {
+    Integer,
+    Float,
+    Boolean,
+    String,
+}
+
+impl ColumnType {
+    // the type of the well known numeric and boolean fields, everything else is a string
+    pub(crate) fn infer(field: &str) -> Self {
+        if INTEGER_FIELDS.contains(&field) {
+            ColumnType::Integer
+        } else if FLOAT_FIELDS.contains(&field) {
+            ColumnType::Float
+        } else if BOOLEAN_FIELDS.contains(&field) {
+            ColumnType::Boolean
+        } else {
+            ColumnType::String
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(column_type: &str) -> Result<Self, Self::Err> {
+        match column_type {
+            "int" => Ok(ColumnType::Integer),
+            "float" => Ok(ColumnType::Float),
+            "bool" => Ok(ColumnType::Boolean),
+            "string" => Ok(ColumnType::String),
+            _ => Err(format!(
+                "Unknown column type {}, must be one of int, float, bool, string",
+                column_type
+            )),
+        }
+    }
+}
+
+// conversions used by typed outputs, values that do not fit the type become null
+pub(crate) fn parse_integer(value: &str) -> Option<i64> {
+    let value = value.trim();
+    value
+        .parse::<i64>()
+        .ok()
+        .or_else(|| value.parse::<f64>().ok().map(|float| float as i64))
+}
+
+pub(crate) fn parse_float(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok()
+}
+
+pub(crate) fn parse_boolean(value: &str) -> Option<bool> {
+    match value {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub(crate) struct Column {
+    pub(crate) name: String,
+    pub(crate) column_type: ColumnType,
+}
+
+// assigns a type to each column, either from --schema (in the format <field>:<type>) or inferred
+// from the field name
+pub(crate) fn typed_columns(names: &[String], schema: &[String]) -> Result<Vec<Column>, String> {
+    let mut declared: Vec<(&str, ColumnType)> = Vec::new();
+    for entry in schema {
+        let (name, column_type) = entry
+            .split_once(':')
+            .ok_or_else(|| format!("Schema entry {} is not in the format <field>:<type>", entry))?;
+        declared.push((name, column_type.parse()?));
+    }
+    Ok(names
+        .iter()
+        .map(|name| Column {
+            name: name.clone(),
+            column_type: declared
+                .iter()
+                .find(|(declared_name, _)| declared_name == name)
+                .map(|(_, column_type)| *column_type)
+                .unwrap_or_else(|| ColumnType::infer(name)),
+        })
+        .collect())
+}
+
+// everything matched is written through a RecordWriter, which decides how a matched line ends up
+// in the output
+pub(crate) trait RecordWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()>;
+
+    // called once after all inputs have been searched
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+// writes newline delimited JSON, either the matched lines as they are or only the selected fields
+pub(crate) struct JsonWriter {
+    output: CompressedWriter,
+    select: Option<Vec<String>>,
+}
+
+impl JsonWriter {
+    pub(crate) fn new(output: CompressedWriter, select: Option<Vec<String>>) -> Self {
+        Self { output, select }
+    }
+}
+
+impl RecordWriter for JsonWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()> {
+        match &self.select {
+            None => writeln!(self.output, "{}", line),
+            Some(fields) => writeln!(self.output, "{}", project(line, fields)),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.finish()
+    }
+}
+
+// builds a JSON object containing only the given fields. The values are copied from the line
+// without parsing them, nested fields keep their dotted path as key and missing fields are null.
+pub(crate) fn project(line: &str, fields: &[String]) -> String {
+    let mut projected = String::with_capacity(line.len() / 4);
+    projected.push('{');
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            projected.push(',');
+        }
+        projected.push('"');
+        projected.push_str(&escape(field));
+        projected.push_str("\":");
+        projected.push_str(find_value(line, field).unwrap_or("null"));
+    }
+    projected.push('}');
+    projected
+}
+
+// writes the selected columns as CSV or TSV with a header row. Strings are unescaped and quoted
+// where needed (e.g. a body containing newlines or quotes), null or missing fields are empty.
+pub(crate) struct CsvWriter {
+    // only None after finish, the csv writer has to be taken apart to finish the compression
+    writer: Option<csv::Writer<CompressedWriter>>,
+    columns: Vec<String>,
+}
+
+impl CsvWriter {
+    pub(crate) fn new(
+        output: CompressedWriter,
+        delimiter: u8,
+        columns: Vec<String>,
+        write_header: bool,
+    ) -> io::Result<Self> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(output);
+        if write_header {
+            writer.write_record(&columns)?;
+        }
+        Ok(Self {
+            writer: Some(writer),
+            columns,
+        })
+    }
+}
+
+impl RecordWriter for CsvWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()> {
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("CSV writer is already finished"))?;
+        for column in &self.columns {
+            match find_value(line, column) {
+                None | Some("null") => writer.write_field("")?,
+                Some(raw) => writer.write_field(value_text(raw).as_bytes())?,
+            }
+        }
+        writer.write_record(None::<&[u8]>)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(writer) => writer
+                .into_inner()
+                .map_err(|e| io::Error::other(e.to_string()))?
+                .finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // an output that can still be read after the writer has taken it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_table(delimiter: u8, columns: &[&str], lines: &[&str]) -> String {
+        let buffer = SharedBuffer::default();
+        let output = CompressedWriter::new(Box::new(buffer.clone()), Compression::None, None, 1);
+        let columns = columns.iter().map(|column| column.to_string()).collect();
+        let mut writer = CsvWriter::new(output.unwrap(), delimiter, columns, true).unwrap();
+        for line in lines {
+            writer.write_record(line).unwrap();
+        }
+        writer.finish().unwrap();
+        let table = buffer.0.borrow().clone();
+        String::from_utf8(table).unwrap()
+    }
+
+    #[test]
+    fn projects_selected_fields() {
+        let line = r#"{"id":"x1","media":{"type":"youtube.com"},"score":3}"#;
+        let fields = ["score", "media.type", "missing", r#"odd"\name"#].map(String::from);
+        assert_eq!(
+            project(line, &fields),
+            r#"{"score":3,"media.type":"youtube.com","missing":null,"odd\"\\name":null}"#
+        );
+    }
+
+    #[test]
+    fn csv_quotes_text_where_needed() {
+        let lines = [
+            r#"{"id":"a","body":"line one\nline two","score":1}"#,
+            r#"{"id":"b","body":"say \"hi\", then leave","score":null}"#,
+            r#"{"id":"c","body":"plain","score":-2}"#,
+        ];
+        assert_eq!(
+            write_table(b',', &["id", "body", "score", "missing"], &lines),
+            "id,body,score,missing\n\
+             a,\"line one\nline two\",1,\n\
+             b,\"say \"\"hi\"\", then leave\",,\n\
+             c,plain,-2,\n"
+        );
+    }
+
+    #[test]
+    fn tsv_quotes_tabs_but_not_commas() {
+        let lines = [r#"{"id":"a","body":"tab\there, comma"}"#];
+        assert_eq!(
+            write_table(b'\t', &["id", "body"], &lines),
+            "id\tbody\na\t\"tab\there, comma\"\n"
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use crate::json::{find_value, value_text};
+use crate::output::{parse_boolean, parse_float, parse_integer, Column, ColumnType, RecordWriter};
+use arrow_array::builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// rows are collected into record batches of this size before they are handed to the writer
+const BATCH_SIZE: usize = 65_536;
+const ROW_GROUP_SIZE: usize = 1_048_576;
+// a partition is written to a new file once it has buffered this many rows
+const ROWS_PER_PARTITION_FILE: usize = 1_048_576;
+// all partition buffers are written out once they hold this many bytes of lines in total
+const PARTITION_BUFFER_BYTES: usize = 512 * 1024 * 1024;
+// used for rows where the partition field is missing, the same name hive and spark use
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Partitioning {
+    Subreddit,
+    Month,
+}
+
+impl FromStr for Partitioning {
+    type Err = String;
+
+    fn from_str(partitioning: &str) -> Result<Self, Self::Err> {
+        match partitioning {
+            "subreddit" => Ok(Partitioning::Subreddit),
+            "month" => Ok(Partitioning::Month),
+            _ => Err(format!(
+                "Unknown partitioning {}, must be subreddit or month",
+                partitioning
+            )),
+        }
+    }
+}
+
+impl Partitioning {
+    fn column_name(&self) -> &'static str {
+        match self {
+            Partitioning::Subreddit => "subreddit",
+            Partitioning::Month => "month",
+        }
+    }
+
+    // the partition a line belongs to, made safe to use as a directory name
+    fn partition_of(&self, line: &str) -> String {
+        let value = match self {
+            Partitioning::Subreddit => find_value(line, "subreddit")
+                .filter(|raw| *raw != "null")
+                .map(|raw| value_text(raw).to_string()),
+            Partitioning::Month => find_value(line, "created_utc")
+                .and_then(|raw| value_text(raw).trim().parse::<f64>().ok())
+                .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp as i64, 0))
+                .map(|datetime| datetime.format("%Y-%m").to_string()),
+        };
+        match value {
+            Some(value) if !value.is_empty() => value
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || c == '_' || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect(),
+            _ => DEFAULT_PARTITION.to_string(),
+        }
+    }
+}
+
+fn schema_of(columns: &[Column]) -> SchemaRef {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.column_type {
+                ColumnType::Integer => DataType::Int64,
+                ColumnType::Float => DataType::Float64,
+                ColumnType::Boolean => DataType::Boolean,
+                ColumnType::String => DataType::Utf8,
+            };
+            Field::new(&column.name, data_type, true)
+        })
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+fn writer_properties() -> WriterProperties {
+    WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .set_max_row_group_row_count(Some(ROW_GROUP_SIZE))
+        .build()
+}
+
+// builds a record batch from the matched lines. Values that do not fit the column type (e.g. a
+// string in an integer column) are written as null.
+fn build_batch(
+    schema: &SchemaRef,
+    columns: &[Column],
+    lines: &[String],
+) -> io::Result<RecordBatch> {
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+    for column in columns {
+        let values = lines.iter().map(|line| {
+            find_value(line, &column.name)
+                .filter(|raw| *raw != "null")
+                .map(value_text)
+        });
+        let array: ArrayRef = match column.column_type {
+            ColumnType::Integer => {
+                let mut builder = Int64Builder::with_capacity(lines.len());
+                for value in values {
+                    builder.append_option(value.and_then(|value| parse_integer(&value)));
+                }
+                Arc::new(builder.finish())
+            }
+            ColumnType::Float => {
+                let mut builder = Float64Builder::with_capacity(lines.len());
+                for value in values {
+                    builder.append_option(value.and_then(|value| parse_float(&value)));
+                }
+                Arc::new(builder.finish())
+            }
+            ColumnType::Boolean => {
+                let mut builder = BooleanBuilder::with_capacity(lines.len());
+                for value in values {
+                    builder.append_option(value.and_then(|value| parse_boolean(&value)));
+                }
+                Arc::new(builder.finish())
+            }
+            ColumnType::String => {
+                let mut builder = StringBuilder::with_capacity(lines.len(), lines.len() * 16);
+                for value in values {
+                    builder.append_option(value);
+                }
+                Arc::new(builder.finish())
+            }
+        };
+        arrays.push(array);
+    }
+    RecordBatch::try_new(schema.clone(), arrays).map_err(io::Error::other)
+}
+
+// writes all matches into a single parquet file
+pub(crate) struct ParquetWriter {
+    writer: Option<ArrowWriter<File>>,
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    pending: Vec<String>,
+}
+
+impl ParquetWriter {
+    pub(crate) fn new(output: File, columns: Vec<Column>) -> io::Result<Self> {
+        let schema = schema_of(&columns);
+        let writer = ArrowWriter::try_new(output, schema.clone(), Some(writer_properties()))
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            writer: Some(writer),
+            schema,
+            columns,
+            pending: Vec::with_capacity(BATCH_SIZE),
+        })
+    }
+
+    fn flush_batch(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let batch = build_batch(&self.schema, &self.columns, &self.pending)?;
+        self.pending.clear();
+        match self.writer.as_mut() {
+            Some(writer) => writer.write(&batch).map_err(io::Error::other),
+            None => Err(io::Error::other("Parquet writer is already closed")),
+        }
+    }
+}
+
+impl RecordWriter for ParquetWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()> {
+        self.pending.push(line.to_string());
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush_batch()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+// writes matches into a hive style directory layout, e.g. <output>/subreddit=news/part-00000.parquet.
+// Rows are buffered per partition and each buffer is written as its own file once it is large
+// enough, so the number of open files stays low even with thousands of subreddits.
+pub(crate) struct PartitionedParquetWriter {
+    directory: PathBuf,
+    partitioning: Partitioning,
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    buffers: HashMap<String, Vec<String>>,
+    buffered_bytes: usize,
+    file_counts: HashMap<String, usize>,
+}
+
+impl PartitionedParquetWriter {
+    pub(crate) fn new(
+        directory: PathBuf,
+        partitioning: Partitioning,
+        columns: Vec<Column>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            partitioning,
+            schema: schema_of(&columns),
+            columns,
+            buffers: HashMap::new(),
+            buffered_bytes: 0,
+            file_counts: HashMap::new(),
+        })
+    }
+
+    fn write_partition(&mut self, partition: &str) -> io::Result<()> {
+        let lines = match self.buffers.remove(partition) {
+            Some(lines) if !lines.is_empty() => lines,
+            _ => return Ok(()),
+        };
+        self.buffered_bytes -= lines.iter().map(|line| line.len()).sum::<usize>();
+
+        let directory =
+            self.directory
+                .join(format!("{}={}", self.partitioning.column_name(), partition));
+        fs::create_dir_all(&directory)?;
+        let file_count = self.file_counts.entry(partition.to_string()).or_insert(0);
+        let file = File::create(directory.join(format!("part-{:05}.parquet", file_count)))?;
+        *file_count += 1;
+
+        let mut writer = ArrowWriter::try_new(file, self.schema.clone(), Some(writer_properties()))
+            .map_err(io::Error::other)?;
+        for batch_lines in lines.chunks(BATCH_SIZE) {
+            let batch = build_batch(&self.schema, &self.columns, batch_lines)?;
+            writer.write(&batch).map_err(io::Error::other)?;
+        }
+        writer.close().map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn write_all_partitions(&mut self) -> io::Result<()> {
+        let partitions: Vec<String> = self.buffers.keys().cloned().collect();
+        for partition in partitions {
+            self.write_partition(&partition)?;
+        }
+        Ok(())
+    }
+}
+
+impl RecordWriter for PartitionedParquetWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()> {
+        let partition = self.partitioning.partition_of(line);
+        let buffer = self.buffers.entry(partition.clone()).or_default();
+        buffer.push(line.to_string());
+        self.buffered_bytes += line.len();
+        if buffer.len() >= ROWS_PER_PARTITION_FILE {
+            self.write_partition(&partition)?;
+        }
+        if self.buffered_bytes >= PARTITION_BUFFER_BYTES {
+            self.write_all_partitions()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_all_partitions()
+    }
+}
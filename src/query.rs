@@ -0,0 +1,381 @@
+use crate::line_processing::FieldFilter;
+use std::fmt;
+
+// a query is a tree of field filters combined with AND, OR and NOT. It is compiled once from the
+// command line and then evaluated for every line.
+pub(crate) enum Query {
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    Field(FieldFilter),
+}
+
+impl Query {
+    // parses an expression like `subreddit:^politics$ AND (body:vaccin OR body:"mask mandate")`.
+    // NOT binds tighter than AND, which binds tighter than OR. Terms written next to each other
+    // without an operator are combined with AND.
+    pub(crate) fn parse(input: &str) -> Result<Self, String> {
+        let tokens = tokenize(input)?;
+        if tokens.is_empty() {
+            return Err("Query is empty".to_string());
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(token) => Err(format!("Unexpected {} in query", token)),
+        }
+    }
+
+    // the --fields and preset behaviour: a line matches if any of the filters match
+    pub(crate) fn any(filters: Vec<FieldFilter>) -> Self {
+        Query::Or(filters.into_iter().map(Query::Field).collect())
+    }
+
+    // the names of all fields used in the query
+    pub(crate) fn fields(&self) -> Vec<&str> {
+        match self {
+            Query::And(queries) | Query::Or(queries) => {
+                queries.iter().flat_map(|query| query.fields()).collect()
+            }
+            Query::Not(query) => query.fields(),
+            Query::Field(filter) => vec![filter.field.as_str()],
+        }
+    }
+
+    // whether a line in a block of lines could match, used to skip blocks with the block index.
+    // `contains` tells whether the block may contain a value of a field, or None if it doesn't
+    // know. Only filters on exact values are looked up, every other filter may match.
+    pub(crate) fn may_match(&self, contains: &dyn Fn(&str, &str) -> Option<bool>) -> bool {
+        match self {
+            Query::And(queries) => queries.iter().all(|query| query.may_match(contains)),
+            Query::Or(queries) => queries.iter().any(|query| query.may_match(contains)),
+            // a block without the value can still contain lines matching NOT value
+            Query::Not(_) => true,
+            Query::Field(filter) => match filter.exact_values() {
+                Some(values) => values
+                    .iter()
+                    .any(|value| contains(&filter.field, value).unwrap_or(true)),
+                None => true,
+            },
+        }
+    }
+
+    pub(crate) fn matches(&self, line: &str) -> bool {
+        match self {
+            Query::And(queries) => queries.iter().all(|query| query.matches(line)),
+            Query::Or(queries) => queries.iter().any(|query| query.matches(line)),
+            Query::Not(query) => !query.matches(line),
+            Query::Field(filter) => filter.matches(line),
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Query::And(queries) | Query::Or(queries) => {
+                let operator = if matches!(self, Query::And(_)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                let parts: Vec<String> = queries.iter().map(|query| query.to_string()).collect();
+                write!(f, "({})", parts.join(operator))
+            }
+            Query::Not(query) => write!(f, "NOT {}", query),
+            Query::Field(filter) => write!(f, "{}", filter),
+        }
+    }
+}
+
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Term(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::LeftParen => write!(f, "'('"),
+            Token::RightParen => write!(f, "')'"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::Term(term) => write!(f, "'{}'", term),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LeftParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RightParen);
+            i += 1;
+        } else {
+            let start = i;
+            while i < chars.len()
+                && !chars[i].is_whitespace()
+                && !matches!(chars[i], ':' | '<' | '>' | '=' | '(' | ')')
+            {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if i >= chars.len() || !matches!(chars[i], ':' | '<' | '>' | '=') {
+                match word.to_uppercase().as_str() {
+                    "AND" => tokens.push(Token::And),
+                    "OR" => tokens.push(Token::Or),
+                    "NOT" => tokens.push(Token::Not),
+                    _ => {
+                        return Err(format!(
+                            "Expected <field>:<regex> or <field><operator><number> in query, found '{}'",
+                            word
+                        ))
+                    }
+                }
+                continue;
+            }
+            // keep the operator (: for regexes, or a comparison), the value is either quoted or
+            // runs until the next whitespace
+            let operator_start = i;
+            while i < chars.len() && matches!(chars[i], ':' | '<' | '>' | '=') {
+                i += 1;
+            }
+            let operator: String = chars[operator_start..i].iter().collect();
+            let value = if i < chars.len() && chars[i] == '"' {
+                read_quoted(&chars, &mut i)?
+            } else {
+                read_bare(&chars, &mut i)
+            };
+            tokens.push(Token::Term(format!("{}{}{}", word, operator, value)));
+        }
+    }
+    Ok(tokens)
+}
+
+// reads a double quoted value, \" inside the quotes is a literal quote. Other escapes are kept as
+// they are so they still reach the regex.
+fn read_quoted(chars: &[char], i: &mut usize) -> Result<String, String> {
+    let mut value = String::new();
+    *i += 1;
+    while *i < chars.len() {
+        match chars[*i] {
+            '"' => {
+                *i += 1;
+                return Ok(value);
+            }
+            '\\' if chars.get(*i + 1) == Some(&'"') => {
+                value.push('"');
+                *i += 2;
+            }
+            c => {
+                value.push(c);
+                *i += 1;
+            }
+        }
+    }
+    Err("Unterminated quote in query".to_string())
+}
+
+// reads an unquoted value. Parentheses belonging to the regex are kept as long as they are
+// balanced, so `body:(cat|dog))` ends the value before the last parenthesis.
+fn read_bare(chars: &[char], i: &mut usize) -> String {
+    let mut value = String::new();
+    let mut depth = 0;
+    while *i < chars.len() && !chars[*i].is_whitespace() {
+        let c = chars[*i];
+        if c == '\\' && *i + 1 < chars.len() {
+            value.push(c);
+            value.push(chars[*i + 1]);
+            *i += 2;
+            continue;
+        }
+        if c == '(' {
+            depth += 1;
+        } else if c == ')' {
+            if depth == 0 {
+                break;
+            }
+            depth -= 1;
+        }
+        value.push(c);
+        *i += 1;
+    }
+    value
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn parse_or(&mut self) -> Result<Query, String> {
+        let mut queries = vec![self.parse_and()?];
+        while let Some(Token::Or) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            queries.push(self.parse_and()?);
+        }
+        Ok(if queries.len() == 1 {
+            queries.pop().unwrap()
+        } else {
+            Query::Or(queries)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Query, String> {
+        let mut queries = vec![self.parse_unary()?];
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(Token::And) => {
+                    self.pos += 1;
+                    queries.push(self.parse_unary()?);
+                }
+                // implicit AND between adjacent terms
+                Some(Token::Term(_)) | Some(Token::LeftParen) | Some(Token::Not) => {
+                    queries.push(self.parse_unary()?);
+                }
+                _ => break,
+            }
+        }
+        Ok(if queries.len() == 1 {
+            queries.pop().unwrap()
+        } else {
+            Query::And(queries)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Query::Not(Box::new(self.parse_unary()?)))
+            }
+            Some(Token::LeftParen) => {
+                self.pos += 1;
+                let query = self.parse_or()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::RightParen) => {
+                        self.pos += 1;
+                        Ok(query)
+                    }
+                    _ => Err("Missing closing parenthesis in query".to_string()),
+                }
+            }
+            Some(Token::Term(term)) => {
+                let filter = FieldFilter::parse(term)?;
+                self.pos += 1;
+                Ok(Query::Field(filter))
+            }
+            Some(token) => Err(format!("Unexpected {} in query", token)),
+            None => Err("Query ends unexpectedly".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(input: &str) -> Vec<String> {
+        tokenize(input)
+            .unwrap()
+            .iter()
+            .map(|token| token.to_string())
+            .collect()
+    }
+
+    fn parsed(input: &str) -> String {
+        Query::parse(input).unwrap().to_string()
+    }
+
+    #[test]
+    fn tokenizes_operators_and_terms() {
+        assert_eq!(
+            terms("(subreddit:news or NOT score>=10) And author:bob"),
+            [
+                "'('",
+                "'subreddit:news'",
+                "OR",
+                "NOT",
+                "'score>=10'",
+                "')'",
+                "AND",
+                "'author:bob'"
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_values_keep_whitespace_and_escaped_quotes() {
+        assert_eq!(terms(r#"body:"mask mandate""#), ["'body:mask mandate'"]);
+        assert_eq!(
+            terms(r#"body:"say \"hi\" (twice)" author:x"#),
+            [r#"'body:say "hi" (twice)'"#, "'author:x'"]
+        );
+        // other escapes are left for the regex
+        assert_eq!(terms(r#"body:"a\.b""#), [r"'body:a\.b'"]);
+        assert!(tokenize(r#"body:"open"#).is_err());
+    }
+
+    #[test]
+    fn bare_regexes_keep_balanced_parentheses() {
+        assert_eq!(terms("body:(cat|dog)"), ["'body:(cat|dog)'"]);
+        assert_eq!(
+            terms("(body:(cat|dog))"),
+            ["'('", "'body:(cat|dog)'", "')'"]
+        );
+        assert_eq!(
+            terms("(body:a(b(c)d)e)"),
+            ["'('", "'body:a(b(c)d)e'", "')'"]
+        );
+        // escaped parentheses don't count
+        assert_eq!(terms(r"(body:\))"), ["'('", r"'body:\)'", "')'"]);
+    }
+
+    #[test]
+    fn precedence_and_implicit_and() {
+        assert_eq!(
+            parsed("subreddit:a OR subreddit:b AND NOT body:c"),
+            "(subreddit:a OR (subreddit:b AND NOT body:c))"
+        );
+        assert_eq!(
+            parsed("(subreddit:a OR subreddit:b) body:c"),
+            "((subreddit:a OR subreddit:b) AND body:c)"
+        );
+        assert_eq!(parsed("NOT NOT score=1"), "NOT NOT score=1");
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        assert!(Query::parse("").is_err());
+        assert!(Query::parse("news").is_err());
+        assert!(Query::parse("(subreddit:a").is_err());
+        assert!(Query::parse("subreddit:a)").is_err());
+        assert!(Query::parse("subreddit:a AND").is_err());
+        assert!(Query::parse("OR subreddit:a").is_err());
+    }
+
+    #[test]
+    fn matches_lines() {
+        let query =
+            Query::parse(r#"subreddit:^news$ AND (body:"mask mandate" OR score>=10)"#).unwrap();
+        assert!(query.matches(r#"{"subreddit":"News","body":"a Mask Mandate","score":1}"#));
+        assert!(query.matches(r#"{"subreddit":"news","body":"","score":12}"#));
+        assert!(!query.matches(r#"{"subreddit":"worldnews","body":"mask mandate","score":12}"#));
+        assert!(!query.matches(r#"{"subreddit":"news","body":"masks","score":9}"#));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::dates::TimeBucket;
+use crate::json::{find_value, value_text};
+use crate::output::RecordWriter;
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+// how matches are sampled, sampling happens after filtering so it draws from the query result
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Sampling {
+    // keep each match with the given probability (Bernoulli sampling)
+    Rate(f64),
+    // keep a uniform random sample of exactly this many matches (reservoir sampling)
+    Size(usize),
+    // keep a uniform random sample of up to this many matches for every subreddit, author or
+    // time bucket
+    Stratified(Stratum, usize),
+}
+
+impl fmt::Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sampling::Rate(rate) => write!(f, "rate {}", rate),
+            Sampling::Size(size) => write!(f, "{} matches", size),
+            Sampling::Stratified(stratum, size) => write!(f, "{} matches per {}", size, stratum),
+        }
+    }
+}
+
+// what stratified samples are grouped by
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Stratum {
+    Subreddit,
+    Author,
+    Day,
+    Week,
+    Month,
+}
+
+impl FromStr for Stratum {
+    type Err = String;
+
+    fn from_str(stratum: &str) -> Result<Self, Self::Err> {
+        match stratum {
+            "subreddit" => Ok(Stratum::Subreddit),
+            "author" => Ok(Stratum::Author),
+            "day" => Ok(Stratum::Day),
+            "week" => Ok(Stratum::Week),
+            "month" => Ok(Stratum::Month),
+            _ => Err(format!(
+                "Unknown stratum {}, must be subreddit, author, day, week or month",
+                stratum
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Stratum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stratum::Subreddit => "subreddit",
+            Stratum::Author => "author",
+            Stratum::Day => "day",
+            Stratum::Week => "week",
+            Stratum::Month => "month",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Stratum {
+    // the stratum a line belongs to, weeks are ISO weeks like 2016-W35. Lines without the field
+    // are grouped together under an empty key.
+    fn key_of(&self, line: &str) -> String {
+        let bucket = match self {
+            Stratum::Subreddit => return text_of(line, "subreddit"),
+            Stratum::Author => return text_of(line, "author"),
+            Stratum::Day => TimeBucket::Day,
+            Stratum::Week => TimeBucket::Week,
+            Stratum::Month => TimeBucket::Month,
+        };
+        bucket.label_of(line).unwrap_or_default()
+    }
+}
+
+fn text_of(line: &str, field: &str) -> String {
+    find_value(line, field)
+        .filter(|raw| *raw != "null")
+        .map(|raw| value_text(raw).to_string())
+        .unwrap_or_default()
+}
+
+// a uniform random sample of a fixed size, filled with algorithm R: the n-th line offered
+// replaces a random line of the reservoir with a probability of size/n
+struct Reservoir {
+    seen: u64,
+    // the position of each line among all matches, used to restore the original order
+    lines: Vec<(u64, String)>,
+}
+
+impl Reservoir {
+    fn new() -> Self {
+        Self {
+            seen: 0,
+            lines: Vec::new(),
+        }
+    }
+
+    fn offer(&mut self, index: u64, line: &str, size: usize, rng: &mut StdRng) {
+        self.seen += 1;
+        if self.lines.len() < size {
+            self.lines.push((index, line.to_string()));
+        } else {
+            let slot = rng.random_range(0..self.seen) as usize;
+            if slot < size {
+                self.lines[slot] = (index, line.to_string());
+            }
+        }
+    }
+}
+
+// wraps the output and only passes a random sample of the matches on to it. Reservoir samples
+// are held in memory until the end of the run and then written in their original order.
+pub(crate) struct SamplingWriter {
+    output: Box<dyn RecordWriter>,
+    sampling: Sampling,
+    rng: StdRng,
+    seen: u64,
+    sampled: u64,
+    // one reservoir per stratum, unstratified samples use a single reservoir with an empty key
+    reservoirs: HashMap<String, Reservoir>,
+}
+
+impl SamplingWriter {
+    pub(crate) fn new(output: Box<dyn RecordWriter>, sampling: Sampling, seed: u64) -> Self {
+        Self {
+            output,
+            sampling,
+            rng: StdRng::seed_from_u64(seed),
+            seen: 0,
+            sampled: 0,
+            reservoirs: HashMap::new(),
+        }
+    }
+}
+
+impl RecordWriter for SamplingWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()> {
+        let index = self.seen;
+        self.seen += 1;
+        let (key, size) = match self.sampling {
+            Sampling::Rate(rate) => {
+                if self.rng.random::<f64>() < rate {
+                    self.sampled += 1;
+                    self.output.write_record(line)?;
+                }
+                return Ok(());
+            }
+            Sampling::Size(size) => (String::new(), size),
+            Sampling::Stratified(stratum, size) => (stratum.key_of(line), size),
+        };
+        self.reservoirs
+            .entry(key)
+            .or_insert_with(Reservoir::new)
+            .offer(index, line, size, &mut self.rng);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let strata = self.reservoirs.len();
+        let mut lines: Vec<(u64, String)> = self
+            .reservoirs
+            .drain()
+            .flat_map(|(_, reservoir)| reservoir.lines)
+            .collect();
+        lines.sort_unstable_by_key(|(index, _)| *index);
+        for (_, line) in lines {
+            self.sampled += 1;
+            self.output.write_record(&line)?;
+        }
+        self.output.finish()?;
+        if let Sampling::Stratified(stratum, _) = self.sampling {
+            status!(
+                "Sampled {} of {} matched lines from {} {} strata",
+                self.sampled,
+                self.seen,
+                strata,
+                stratum
+            );
+        } else {
+            status!("Sampled {} of {} matched lines", self.sampled, self.seen);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // keeps the lines passed on by the sampling writer
+    #[derive(Clone, Default)]
+    struct Collected(Rc<RefCell<Vec<String>>>);
+
+    impl RecordWriter for Collected {
+        fn write_record(&mut self, line: &str) -> io::Result<()> {
+            self.0.borrow_mut().push(line.to_string());
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn lines(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| format!(r#"{{"id":{},"subreddit":"sub{}"}}"#, i, i % 3))
+            .collect()
+    }
+
+    fn sample(sampling: Sampling, seed: u64, lines: &[String]) -> Vec<String> {
+        let collected = Collected::default();
+        let mut writer = SamplingWriter::new(Box::new(collected.clone()), sampling, seed);
+        for line in lines {
+            writer.write_record(line).unwrap();
+        }
+        writer.finish().unwrap();
+        collected.0.take()
+    }
+
+    fn ids(lines: &[String]) -> Vec<u64> {
+        lines
+            .iter()
+            .map(|line| find_value(line, "id").unwrap().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reservoir_keeps_the_size_and_the_original_order() {
+        let lines = lines(1000);
+        let sampled = ids(&sample(Sampling::Size(10), 7, &lines));
+        assert_eq!(sampled.len(), 10);
+        assert!(sampled.windows(2).all(|pair| pair[0] < pair[1]));
+        // the sample is spread over all matches, not just the first ones
+        assert!(sampled.iter().any(|id| *id >= 100));
+        // fewer matches than the size are all kept
+        assert_eq!(sample(Sampling::Size(2000), 7, &lines), lines);
+    }
+
+    #[test]
+    fn samples_repeat_with_the_same_seed() {
+        let lines = lines(1000);
+        for sampling in [Sampling::Size(10), Sampling::Rate(0.05)] {
+            let first = sample(sampling, 42, &lines);
+            assert_eq!(sample(sampling, 42, &lines), first);
+            assert_ne!(sample(sampling, 43, &lines), first);
+        }
+    }
+
+    #[test]
+    fn rates_of_none_and_all() {
+        let lines = lines(100);
+        assert!(sample(Sampling::Rate(0.0), 1, &lines).is_empty());
+        assert_eq!(sample(Sampling::Rate(1.0), 1, &lines), lines);
+    }
+
+    #[test]
+    fn stratified_sampling_per_key() {
+        let mut lines = lines(300);
+        // lines without the field are sampled together
+        lines.push(r#"{"id":300}"#.to_string());
+        lines.push(r#"{"id":301,"subreddit":null}"#.to_string());
+        let sampled = sample(Sampling::Stratified(Stratum::Subreddit, 1), 5, &lines);
+        assert_eq!(sampled.len(), 4);
+        let mut keys: Vec<String> = sampled
+            .iter()
+            .map(|line| Stratum::Subreddit.key_of(line))
+            .collect();
+        assert!(ids(&sampled).windows(2).all(|pair| pair[0] < pair[1]));
+        keys.sort();
+        assert_eq!(keys, ["", "sub0", "sub1", "sub2"]);
+
+        let sampled = sample(Sampling::Stratified(Stratum::Subreddit, 5), 5, &lines);
+        assert_eq!(sampled.len(), 3 * 5 + 2);
+    }
+}
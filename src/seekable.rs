@@ -0,0 +1,413 @@
+use crate::frames::{FrameReader, MAX_FRAME_SIZE};
+use crate::inputs::CountingReader;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// the zstd seekable format: the data is split into independent zstd frames, followed by a seek
+// table in a skippable frame that records the compressed and decompressed size of every frame.
+// Any zstd decoder can read these files as usual, readers that know the format can jump to a
+// frame without decompressing what comes before it.
+// See https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md
+
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+// number of frames, the descriptor byte and the magic number
+const FOOTER_SIZE: u64 = 9;
+// the skippable frame magic and frame size before the entries
+const SKIPPABLE_HEADER_SIZE: u64 = 8;
+// the descriptor bit for entries with a checksum, which is not used here
+const CHECKSUM_FLAG: u8 = 0x80;
+
+#[derive(Clone, Copy)]
+pub(crate) struct Frame {
+    pub(crate) compressed_offset: u64,
+    pub(crate) compressed_size: u64,
+    pub(crate) decompressed_offset: u64,
+    pub(crate) decompressed_size: u64,
+}
+
+pub(crate) struct SeekTable {
+    pub(crate) frames: Vec<Frame>,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl SeekTable {
+    // reads the seek table at the end of the file, returns None if the file doesn't have one. The
+    // position of the file is changed.
+    pub(crate) fn read(file: &mut File) -> io::Result<Option<Self>> {
+        let file_size = file.metadata()?.len();
+        if file_size < SKIPPABLE_HEADER_SIZE + FOOTER_SIZE {
+            return Ok(None);
+        }
+        let mut footer = [0; FOOTER_SIZE as usize];
+        file.seek(SeekFrom::Start(file_size - FOOTER_SIZE))?;
+        file.read_exact(&mut footer)?;
+        if read_u32(&footer[5..9]) != SEEKABLE_MAGIC {
+            return Ok(None);
+        }
+        let frame_count = read_u32(&footer[0..4]) as u64;
+        let entry_size = if footer[4] & CHECKSUM_FLAG != 0 {
+            12
+        } else {
+            8
+        };
+        let table_size = SKIPPABLE_HEADER_SIZE + frame_count * entry_size + FOOTER_SIZE;
+        if table_size > file_size {
+            return Ok(None);
+        }
+
+        let mut table = vec![0; (table_size - FOOTER_SIZE) as usize];
+        file.seek(SeekFrom::Start(file_size - table_size))?;
+        file.read_exact(&mut table)?;
+        if read_u32(&table[0..4]) != SKIPPABLE_MAGIC {
+            return Ok(None);
+        }
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+        for entry in table[SKIPPABLE_HEADER_SIZE as usize..].chunks_exact(entry_size as usize) {
+            let frame = Frame {
+                compressed_offset,
+                compressed_size: read_u32(&entry[0..4]) as u64,
+                decompressed_offset,
+                decompressed_size: read_u32(&entry[4..8]) as u64,
+            };
+            compressed_offset += frame.compressed_size;
+            decompressed_offset += frame.decompressed_size;
+            frames.push(frame);
+        }
+        // the frames have to end where the seek table starts
+        if compressed_offset != file_size - table_size {
+            return Ok(None);
+        }
+        Ok(Some(Self { frames }))
+    }
+
+    // whether every frame can be decompressed in one piece, seekable files written by other tools
+    // may use larger frames than the convert subcommand
+    pub(crate) fn frames_fit(&self) -> bool {
+        self.frames
+            .iter()
+            .all(|frame| frame.decompressed_size <= MAX_FRAME_SIZE)
+    }
+
+    fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        let entries_size = self.frames.len() as u32 * 8;
+        output.write_all(&SKIPPABLE_MAGIC.to_le_bytes())?;
+        output.write_all(&(entries_size + FOOTER_SIZE as u32).to_le_bytes())?;
+        for frame in &self.frames {
+            output.write_all(&(frame.compressed_size as u32).to_le_bytes())?;
+            output.write_all(&(frame.decompressed_size as u32).to_le_bytes())?;
+        }
+        output.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        output.write_all(&[0])?;
+        output.write_all(&SEEKABLE_MAGIC.to_le_bytes())
+    }
+}
+
+// reads a seekable file with a FrameReader. Skipping ahead jumps straight to the frame containing
+// the new position, the frames in between are never decompressed.
+pub(crate) struct SeekableReader {
+    reader: FrameReader,
+    frames: Vec<Frame>,
+    // the decompressed offset of the next byte to read
+    offset: u64,
+    total_size: u64,
+}
+
+impl SeekableReader {
+    pub(crate) fn new(file: CountingReader<File>, table: SeekTable) -> Self {
+        let total_size = table.frames.last().map_or(0, |frame| {
+            frame.decompressed_offset + frame.decompressed_size
+        });
+        let compressed = table
+            .frames
+            .iter()
+            .map(|frame| (frame.compressed_offset, frame.compressed_size))
+            .collect();
+        Self {
+            reader: FrameReader::new(file, compressed),
+            frames: table.frames,
+            offset: 0,
+            total_size,
+        }
+    }
+
+    // skips the given number of decompressed bytes
+    pub(crate) fn skip(&mut self, bytes: u64) {
+        let bytes = bytes.min(self.total_size - self.offset);
+        if bytes <= self.reader.buffered() as u64 {
+            self.reader.consume(bytes as usize);
+        } else {
+            let offset = self.offset + bytes;
+            let index = self.frames.partition_point(|frame| {
+                frame.decompressed_offset + frame.decompressed_size <= offset
+            });
+            let position = self
+                .frames
+                .get(index)
+                .map_or(0, |frame| offset - frame.decompressed_offset);
+            self.reader.seek(index, position as usize);
+        }
+        self.offset += bytes;
+    }
+}
+
+impl Read for SeekableReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl BufRead for SeekableReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount);
+        self.offset += amount as u64;
+    }
+}
+
+// rewrites an input into the seekable format. Frames end at line boundaries and hold up to
+// `frame_size` bytes of decompressed data each. Frames are compressed in parallel, a batch at a
+// time. Returns the number of frames written.
+pub(crate) fn convert(
+    input_stream: &mut dyn BufRead,
+    output: &Path,
+    frame_size: usize,
+    level: i32,
+) -> io::Result<usize> {
+    let mut output = BufWriter::new(File::create(output)?);
+    let mut table = SeekTable { frames: Vec::new() };
+    let batch_size = rayon::current_num_threads() * 2;
+    let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+    // a line that was read but didn't fit into the previous frame
+    let mut line = Vec::new();
+    loop {
+        let mut batch: Vec<Vec<u8>> = Vec::with_capacity(batch_size);
+        while batch.len() < batch_size {
+            let mut frame = Vec::with_capacity(frame_size);
+            loop {
+                if line.is_empty() && input_stream.read_until(b'\n', &mut line)? == 0 {
+                    break;
+                }
+                // only a single line longer than frame_size makes a frame larger than that
+                if !frame.is_empty() && frame.len() + line.len() > frame_size {
+                    break;
+                }
+                frame.append(&mut line);
+            }
+            if frame.is_empty() {
+                break;
+            }
+            if frame.len() > u32::MAX as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "A line is too long for a single seekable frame",
+                ));
+            }
+            batch.push(frame);
+        }
+        if batch.is_empty() {
+            break;
+        }
+        let compressed: Vec<Vec<u8>> = batch
+            .par_iter()
+            .map(|frame| zstd::bulk::compress(frame, level))
+            .collect::<io::Result<_>>()?;
+        for (frame, compressed) in batch.iter().zip(compressed) {
+            output.write_all(&compressed)?;
+            table.frames.push(Frame {
+                compressed_offset,
+                compressed_size: compressed.len() as u64,
+                decompressed_offset,
+                decompressed_size: frame.len() as u64,
+            });
+            compressed_offset += compressed.len() as u64;
+            decompressed_offset += frame.len() as u64;
+        }
+    }
+    table.write(&mut output)?;
+    output.flush()?;
+    Ok(table.frames.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    // a file in the temporary directory that is removed again when the test ends
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let file_name = format!("reddit-search-{}-{}", std::process::id(), name);
+            Self(std::env::temp_dir().join(file_name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn table(sizes: &[(u64, u64)]) -> SeekTable {
+        let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+        let frames = sizes
+            .iter()
+            .map(|(compressed_size, decompressed_size)| {
+                let frame = Frame {
+                    compressed_offset,
+                    compressed_size: *compressed_size,
+                    decompressed_offset,
+                    decompressed_size: *decompressed_size,
+                };
+                compressed_offset += compressed_size;
+                decompressed_offset += decompressed_size;
+                frame
+            })
+            .collect();
+        SeekTable { frames }
+    }
+
+    // `data` followed by the seek table
+    fn read_table(name: &str, data: &[u8], table: &SeekTable) -> Option<SeekTable> {
+        let path = TempFile::new(name);
+        let mut bytes = data.to_vec();
+        table.write(&mut bytes).unwrap();
+        std::fs::write(&path.0, bytes).unwrap();
+        SeekTable::read(&mut File::open(&path.0).unwrap()).unwrap()
+    }
+
+    fn sizes(table: &SeekTable) -> Vec<(u64, u64, u64, u64)> {
+        table
+            .frames
+            .iter()
+            .map(|frame| {
+                (
+                    frame.compressed_offset,
+                    frame.compressed_size,
+                    frame.decompressed_offset,
+                    frame.decompressed_size,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seek_table_round_trip() {
+        let written = table(&[(3, 10), (0, 0), (5, 7)]);
+        let read = read_table("round-trip", &[0; 8], &written).unwrap();
+        assert_eq!(sizes(&read), sizes(&written));
+        assert_eq!(sizes(&read)[2], (3, 5, 10, 7));
+        assert!(read.frames_fit());
+    }
+
+    #[test]
+    fn empty_seek_table() {
+        let read = read_table("empty", &[], &table(&[])).unwrap();
+        assert!(read.frames.is_empty());
+    }
+
+    #[test]
+    fn rejects_files_without_a_matching_seek_table() {
+        // frames that don't end where the table starts
+        assert!(read_table("too-short", &[0; 7], &table(&[(3, 10), (5, 7)])).is_none());
+        assert!(read_table("too-long", &[0; 9], &table(&[(3, 10), (5, 7)])).is_none());
+
+        let path = TempFile::new("no-table");
+        std::fs::write(&path.0, [0; 64]).unwrap();
+        assert!(SeekTable::read(&mut File::open(&path.0).unwrap())
+            .unwrap()
+            .is_none());
+        std::fs::write(&path.0, [0; 3]).unwrap();
+        assert!(SeekTable::read(&mut File::open(&path.0).unwrap())
+            .unwrap()
+            .is_none());
+
+        // a wrong skippable frame magic
+        let mut bytes = Vec::new();
+        table(&[]).write(&mut bytes).unwrap();
+        bytes[0] ^= 0xFF;
+        std::fs::write(&path.0, bytes).unwrap();
+        assert!(SeekTable::read(&mut File::open(&path.0).unwrap())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn reads_entries_with_checksums() {
+        let path = TempFile::new("checksums");
+        let mut bytes = vec![0; 4];
+        bytes.extend(SKIPPABLE_MAGIC.to_le_bytes());
+        bytes.extend((12 + FOOTER_SIZE as u32).to_le_bytes());
+        for value in [4u32, 9, 0xDEADBEEF] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(1u32.to_le_bytes());
+        bytes.push(CHECKSUM_FLAG);
+        bytes.extend(SEEKABLE_MAGIC.to_le_bytes());
+        std::fs::write(&path.0, bytes).unwrap();
+        let read = SeekTable::read(&mut File::open(&path.0).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(sizes(&read), [(0, 4, 0, 9)]);
+    }
+
+    #[test]
+    fn converted_files_read_back_and_skip() {
+        let lines: String = (0..2000)
+            .map(|i| format!("{{\"id\":{},\"body\":\"line {}\"}}\n", i, i))
+            .collect();
+        let path = TempFile::new("convert.zst");
+        let frame_count = convert(&mut lines.as_bytes(), &path.0, 4096, 3).unwrap();
+        assert!(frame_count > 1);
+
+        // any zstd decoder can read the converted file
+        let decoded = zstd::decode_all(File::open(&path.0).unwrap()).unwrap();
+        assert_eq!(decoded, lines.as_bytes());
+
+        let mut file = File::open(&path.0).unwrap();
+        let table = SeekTable::read(&mut file).unwrap().unwrap();
+        assert_eq!(table.frames.len(), frame_count);
+        // frames end at line boundaries and never exceed the frame size
+        for frame in &table.frames {
+            assert!(frame.decompressed_size <= 4096);
+            let end = (frame.decompressed_offset + frame.decompressed_size) as usize;
+            assert_eq!(lines.as_bytes()[end - 1], b'\n');
+        }
+
+        let counted = Arc::new(AtomicU64::new(0));
+        let mut reader = SeekableReader::new(CountingReader::new(file, counted), table);
+        let mut start = String::new();
+        reader.read_line(&mut start).unwrap();
+        assert_eq!(start, "{\"id\":0,\"body\":\"line 0\"}\n");
+        // skip within the current frame, then across several frames
+        let offset = lines.find("{\"id\":3,").unwrap() - start.len();
+        reader.skip(offset as u64);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"id\":3,\"body\":\"line 3\"}\n");
+        let position = lines.find("{\"id\":1500,").unwrap();
+        reader.skip((position - lines.find("{\"id\":4,").unwrap()) as u64);
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, lines[position..]);
+        // skipping past the end leaves nothing to read
+        reader.skip(100);
+        assert_eq!(reader.read(&mut [0; 8]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::json::{find_value, value_text};
+use crate::output::{parse_boolean, parse_float, parse_integer, Column, ColumnType, RecordWriter};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use std::io;
+
+// rows are inserted in transactions of this size, committing every row would be far too slow
+const ROWS_PER_TRANSACTION: usize = 50_000;
+// indexes are created on these columns if they are part of the table
+const INDEXED_COLUMNS: [&str; 3] = ["author", "subreddit", "created_utc"];
+// the full text index covers these columns if they are part of the table
+const FULL_TEXT_COLUMNS: [&str; 3] = ["body", "title", "selftext"];
+
+// writes matches into a table of a SQLite database, with one column per field and the whole line
+// in the raw column. The indexes (and the optional FTS5 full text index) are built once all
+// matches have been inserted, which is much faster than updating them on every insert.
+pub(crate) struct SqliteWriter {
+    connection: Connection,
+    table: String,
+    columns: Vec<Column>,
+    insert_sql: String,
+    full_text: bool,
+    pending_rows: usize,
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn to_io_error(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+impl SqliteWriter {
+    pub(crate) fn new(
+        path: &str,
+        table: &str,
+        columns: Vec<Column>,
+        full_text: bool,
+    ) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(to_io_error)?;
+        // the database is only written by us and can be recreated from the dumps, so durability is
+        // traded for speed
+        connection
+            .execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")
+            .map_err(to_io_error)?;
+
+        let column_definitions: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                let sql_type = match column.column_type {
+                    ColumnType::Integer | ColumnType::Boolean => "INTEGER",
+                    ColumnType::Float => "REAL",
+                    ColumnType::String => "TEXT",
+                };
+                format!("{} {}", quote(&column.name), sql_type)
+            })
+            .collect();
+        connection
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} ({}, raw TEXT NOT NULL);",
+                quote(table),
+                column_definitions.join(", ")
+            ))
+            .map_err(to_io_error)?;
+
+        let column_names: Vec<String> = columns.iter().map(|column| quote(&column.name)).collect();
+        let placeholders = vec!["?"; columns.len() + 1].join(", ");
+        let insert_sql = format!(
+            "INSERT INTO {} ({}, raw) VALUES ({})",
+            quote(table),
+            column_names.join(", "),
+            placeholders
+        );
+        connection.execute_batch("BEGIN").map_err(to_io_error)?;
+        Ok(Self {
+            connection,
+            table: table.to_string(),
+            columns,
+            insert_sql,
+            full_text,
+            pending_rows: 0,
+        })
+    }
+
+    fn create_indexes(&self) -> rusqlite::Result<()> {
+        for column in INDEXED_COLUMNS {
+            if self.columns.iter().any(|c| c.name == column) {
+                self.connection.execute_batch(&format!(
+                    "CREATE INDEX IF NOT EXISTS {} ON {} ({});",
+                    quote(&format!("{}_{}", self.table, column)),
+                    quote(&self.table),
+                    quote(column)
+                ))?;
+            }
+        }
+        if self.full_text {
+            let text_columns: Vec<String> = FULL_TEXT_COLUMNS
+                .iter()
+                .filter(|column| self.columns.iter().any(|c| c.name == **column))
+                .map(|column| quote(column))
+                .collect();
+            if text_columns.is_empty() {
+                eprintln!("Warning: None of body, title or selftext are written, skipping the full text index.");
+                return Ok(());
+            }
+            // an external content table, the text is only stored once in the main table
+            let fts_table = quote(&format!("{}_fts", self.table));
+            self.connection.execute_batch(&format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS {} USING fts5({}, content={}, content_rowid='rowid');
+                 INSERT INTO {}({}) VALUES('rebuild');",
+                fts_table,
+                text_columns.join(", "),
+                quote(&self.table),
+                fts_table,
+                fts_table
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl RecordWriter for SqliteWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()> {
+        let mut values: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let value = match find_value(line, &column.name) {
+                    None | Some("null") => return Value::Null,
+                    Some(raw) => value_text(raw),
+                };
+                let typed = match column.column_type {
+                    ColumnType::Integer => parse_integer(&value).map(Value::Integer),
+                    ColumnType::Float => parse_float(&value).map(Value::Real),
+                    ColumnType::Boolean => {
+                        parse_boolean(&value).map(|boolean| Value::Integer(boolean as i64))
+                    }
+                    ColumnType::String => Some(Value::Text(value.to_string())),
+                };
+                typed.unwrap_or(Value::Null)
+            })
+            .collect();
+        values.push(Value::Text(line.to_string()));
+
+        self.connection
+            .prepare_cached(&self.insert_sql)
+            .and_then(|mut statement| statement.execute(params_from_iter(values)))
+            .map_err(to_io_error)?;
+        self.pending_rows += 1;
+        if self.pending_rows >= ROWS_PER_TRANSACTION {
+            self.connection
+                .execute_batch("COMMIT; BEGIN;")
+                .map_err(to_io_error)?;
+            self.pending_rows = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.connection
+            .execute_batch("COMMIT")
+            .map_err(to_io_error)?;
+        self.create_indexes().map_err(to_io_error)
+    }
+}
@@ -0,0 +1,206 @@
+use crate::aggregation::COUNT_COLUMN;
+use crate::dates::{created_utc, TimeBucket};
+use crate::json::field_text;
+use crate::output::RecordWriter;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+// passes the matches on to the output and counts them per time bucket of created_utc, optionally
+// split by a field. At the end the counts are written to a CSV report with a row for every bucket
+// between the first and the last match, buckets without matches are written with a count of 0.
+pub(crate) struct TimeseriesWriter {
+    output: Box<dyn RecordWriter>,
+    bucket: TimeBucket,
+    split_by: Option<String>,
+    report: PathBuf,
+    // counts per bucket start and split value, the split value is empty without --split-by
+    counts: BTreeMap<i64, BTreeMap<String, u64>>,
+    splits: BTreeSet<String>,
+    // matches without a usable created_utc can't be placed in a bucket
+    skipped: u64,
+}
+
+impl TimeseriesWriter {
+    pub(crate) fn new(
+        output: Box<dyn RecordWriter>,
+        bucket: TimeBucket,
+        split_by: Option<String>,
+        report: PathBuf,
+    ) -> Self {
+        Self {
+            output,
+            bucket,
+            split_by,
+            report,
+            counts: BTreeMap::new(),
+            splits: BTreeSet::new(),
+            skipped: 0,
+        }
+    }
+
+    fn write_report(&self) -> io::Result<()> {
+        let mut writer = csv::Writer::from_writer(File::create(&self.report)?);
+        let mut header = vec![self.bucket.name()];
+        if let Some(split_by) = self.split_by.as_ref() {
+            header.push(split_by);
+        }
+        header.push(COUNT_COLUMN);
+        writer.write_record(&header)?;
+
+        let (Some(first), Some(last)) = (self.counts.keys().next(), self.counts.keys().next_back())
+        else {
+            return writer.flush();
+        };
+        let mut start = match self.bucket.start(*first) {
+            Some(start) => start,
+            None => return writer.flush(),
+        };
+        let no_counts = BTreeMap::new();
+        while start.timestamp() <= *last {
+            let label = self.bucket.label(start.timestamp()).unwrap_or_default();
+            let counts = self.counts.get(&start.timestamp()).unwrap_or(&no_counts);
+            for split in &self.splits {
+                let count = counts.get(split).copied().unwrap_or(0).to_string();
+                let mut row = vec![label.as_str()];
+                if self.split_by.is_some() {
+                    row.push(split);
+                }
+                row.push(&count);
+                writer.write_record(&row)?;
+            }
+            start = self.bucket.next(start);
+        }
+        writer.flush()
+    }
+}
+
+impl RecordWriter for TimeseriesWriter {
+    fn write_record(&mut self, line: &str) -> io::Result<()> {
+        match created_utc(line).and_then(|timestamp| self.bucket.start(timestamp)) {
+            Some(start) => {
+                let split = match self.split_by.as_ref() {
+                    Some(field) => field_text(line, field)
+                        .filter(|value| value != "null")
+                        .map(|value| value.to_string())
+                        .unwrap_or_default(),
+                    None => String::new(),
+                };
+                if !self.splits.contains(&split) {
+                    self.splits.insert(split.clone());
+                }
+                *self
+                    .counts
+                    .entry(start.timestamp())
+                    .or_default()
+                    .entry(split)
+                    .or_insert(0) += 1;
+            }
+            None => self.skipped += 1,
+        }
+        self.output.write_record(line)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.finish()?;
+        self.write_report()?;
+        if self.skipped > 0 {
+            status!(
+                "Warning: {} matches have no valid created_utc and are missing from the time series.",
+                self.skipped
+            );
+        }
+        status!("Wrote the time series to {}", self.report.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Discard;
+
+    impl RecordWriter for Discard {
+        fn write_record(&mut self, _: &str) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the report written for the lines, created at the given timestamps and in the given
+    // subreddits
+    fn report(bucket: TimeBucket, split_by: Option<&str>, lines: &[(i64, &str)]) -> String {
+        let file_name = format!(
+            "reddit-search-{}-{}-{}.csv",
+            std::process::id(),
+            bucket.name(),
+            split_by.is_some()
+        );
+        let path = std::env::temp_dir().join(file_name);
+        let split_by = split_by.map(String::from);
+        let mut writer = TimeseriesWriter::new(Box::new(Discard), bucket, split_by, path.clone());
+        for (created, subreddit) in lines {
+            let line = format!(
+                r#"{{"created_utc":{},"subreddit":"{}"}}"#,
+                created, subreddit
+            );
+            writer.write_record(&line).unwrap();
+        }
+        // matches without created_utc are left out
+        writer.write_record(r#"{"subreddit":"news"}"#).unwrap();
+        writer.finish().unwrap();
+        let report = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        report
+    }
+
+    // 2016-09-01T00:00:00Z
+    const SEP_1: i64 = 1472688000;
+    const DAY: i64 = 86400;
+
+    #[test]
+    fn fills_empty_buckets_with_zero() {
+        let lines = [
+            (SEP_1 + 10, "news"),
+            (SEP_1 + 3 * DAY + 5, "news"),
+            (SEP_1 + 20, "news"),
+        ];
+        assert_eq!(
+            report(TimeBucket::Day, None, &lines),
+            "day,count\n2016-09-01,2\n2016-09-02,0\n2016-09-03,0\n2016-09-04,1\n"
+        );
+        assert_eq!(
+            report(
+                TimeBucket::Month,
+                None,
+                &[(SEP_1 - 1, "news"), (SEP_1 + 61 * DAY, "news")]
+            ),
+            "month,count\n2016-08,1\n2016-09,0\n2016-10,0\n2016-11,1\n"
+        );
+    }
+
+    #[test]
+    fn fills_every_split_in_every_bucket() {
+        let lines = [(SEP_1, "news"), (SEP_1 + 2 * DAY, "pics")];
+        assert_eq!(
+            report(TimeBucket::Day, Some("subreddit"), &lines),
+            "day,subreddit,count\n\
+             2016-09-01,news,1\n\
+             2016-09-01,pics,0\n\
+             2016-09-02,news,0\n\
+             2016-09-02,pics,0\n\
+             2016-09-03,news,0\n\
+             2016-09-03,pics,1\n"
+        );
+    }
+
+    #[test]
+    fn writes_only_the_header_without_matches() {
+        assert_eq!(report(TimeBucket::Week, None, &[]), "week,count\n");
+    }
+}